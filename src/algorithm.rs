@@ -0,0 +1,235 @@
+use crate::{Error, Result};
+use crc32fast::Hasher as Crc32;
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest as CryptoDigest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{OnceLock, RwLock};
+use xxhash_rust::xxh3::Xxh3;
+use xxhash_rust::xxh32::Xxh32;
+use xxhash_rust::xxh64::Xxh64;
+
+/// 生成清单时默认使用的哈希算法
+pub const DEFAULT_ALGORITHM: &str = "xxh3-128";
+
+/// 逐块输入数据计算哈希, 通过 [`register_algorithm`] 注册新的算法
+pub trait StreamingHasher: Send {
+    fn update(&mut self, data: &[u8]);
+    fn finish(self: Box<Self>) -> Digest;
+    fn digest_len(&self) -> usize;
+}
+
+/// 创建新的哈希计算器, 每个文件调用一次
+pub type HasherFactory = fn() -> Box<dyn StreamingHasher>;
+
+/// 哈希值, 按十六进制小写输出
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Digest(Vec<u8>);
+
+impl Digest {
+    pub fn new(bytes: Vec<u8>) -> Digest {
+        Digest(bytes)
+    }
+
+    /// 解析十六进制的哈希值, 不足摘要长度时按旧版清单补齐前导零
+    pub fn from_hex(hex: &str, digest_len: usize) -> Result<Digest> {
+        let invalid_hash = || Error::InvalidHash {
+            value: hex.to_string(),
+        };
+        if hex.is_empty() || hex.len() > digest_len * 2 || !hex.is_ascii() {
+            return Err(invalid_hash());
+        }
+        // 旧版清单不写前导零, 需要补齐到算法的摘要长度
+        let padded = format!("{:0>width$}", hex, width = digest_len * 2);
+        let mut bytes = Vec::with_capacity(digest_len);
+        for i in (0..padded.len()).step_by(2) {
+            match u8::from_str_radix(&padded[i..i + 2], 16) {
+                Ok(byte) => bytes.push(byte),
+                Err(_) => return Err(invalid_hash()),
+            }
+        }
+        Ok(Digest(bytes))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+struct Xxh3_128(Xxh3);
+
+impl StreamingHasher for Xxh3_128 {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finish(self: Box<Self>) -> Digest {
+        Digest(self.0.digest128().to_be_bytes().to_vec())
+    }
+
+    fn digest_len(&self) -> usize {
+        16
+    }
+}
+
+struct Xxh3_64(Xxh3);
+
+impl StreamingHasher for Xxh3_64 {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finish(self: Box<Self>) -> Digest {
+        Digest(self.0.digest().to_be_bytes().to_vec())
+    }
+
+    fn digest_len(&self) -> usize {
+        8
+    }
+}
+
+struct Xxh64Hasher(Xxh64);
+
+impl StreamingHasher for Xxh64Hasher {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finish(self: Box<Self>) -> Digest {
+        Digest(self.0.digest().to_be_bytes().to_vec())
+    }
+
+    fn digest_len(&self) -> usize {
+        8
+    }
+}
+
+struct Xxh32Hasher(Xxh32);
+
+impl StreamingHasher for Xxh32Hasher {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finish(self: Box<Self>) -> Digest {
+        Digest(self.0.digest().to_be_bytes().to_vec())
+    }
+
+    fn digest_len(&self) -> usize {
+        4
+    }
+}
+
+struct Blake3Hasher(blake3::Hasher);
+
+impl StreamingHasher for Blake3Hasher {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finish(self: Box<Self>) -> Digest {
+        Digest(self.0.finalize().as_bytes().to_vec())
+    }
+
+    fn digest_len(&self) -> usize {
+        blake3::OUT_LEN
+    }
+}
+
+struct Crc32Hasher(Crc32);
+
+impl StreamingHasher for Crc32Hasher {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finish(self: Box<Self>) -> Digest {
+        Digest(self.0.finalize().to_be_bytes().to_vec())
+    }
+
+    fn digest_len(&self) -> usize {
+        4
+    }
+}
+
+// MD5, SHA-1 和 SHA-256 主要用于校验云存储记录的哈希, 例如 rclone hashsum 的输出
+struct CryptoHasher<D>(D);
+
+impl<D: CryptoDigest + Send> StreamingHasher for CryptoHasher<D> {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finish(self: Box<Self>) -> Digest {
+        Digest(self.0.finalize().to_vec())
+    }
+
+    fn digest_len(&self) -> usize {
+        <D as CryptoDigest>::output_size()
+    }
+}
+
+fn algorithm_registry() -> &'static RwLock<HashMap<String, HasherFactory>> {
+    static ALGORITHM_REGISTRY: OnceLock<RwLock<HashMap<String, HasherFactory>>> = OnceLock::new();
+    ALGORITHM_REGISTRY.get_or_init(|| {
+        let mut algorithms: HashMap<String, HasherFactory> = HashMap::new();
+        algorithms.insert(DEFAULT_ALGORITHM.to_string(), || {
+            Box::new(Xxh3_128(Xxh3::new()))
+        });
+        algorithms.insert("xxh3-64".to_string(), || Box::new(Xxh3_64(Xxh3::new())));
+        algorithms.insert("xxh64".to_string(), || Box::new(Xxh64Hasher(Xxh64::new(0))));
+        algorithms.insert("xxh32".to_string(), || Box::new(Xxh32Hasher(Xxh32::new(0))));
+        algorithms.insert("blake3".to_string(), || {
+            Box::new(Blake3Hasher(blake3::Hasher::new()))
+        });
+        algorithms.insert("crc32".to_string(), || Box::new(Crc32Hasher(Crc32::new())));
+        algorithms.insert("md5".to_string(), || Box::new(CryptoHasher(Md5::new())));
+        algorithms.insert("sha1".to_string(), || Box::new(CryptoHasher(Sha1::new())));
+        algorithms.insert("sha256".to_string(), || {
+            Box::new(CryptoHasher(Sha256::new()))
+        });
+        RwLock::new(algorithms)
+    })
+}
+
+/// 注册哈希算法, 同名的算法被替换
+pub fn register_algorithm(name: &str, factory: HasherFactory) {
+    algorithm_registry()
+        .write()
+        .unwrap_or_else(|err| err.into_inner())
+        .insert(name.to_string(), factory);
+}
+
+/// 按名称创建已注册算法的计算器
+pub fn create_hasher(name: &str) -> Result<Box<dyn StreamingHasher>> {
+    match algorithm_registry()
+        .read()
+        .unwrap_or_else(|err| err.into_inner())
+        .get(name)
+    {
+        Some(factory) => Ok(factory()),
+        None => Err(Error::UnsupportedAlgorithm(name.to_string())),
+    }
+}
+
+/// 已注册的算法名称, 按名称排序
+pub fn algorithm_names() -> Vec<String> {
+    let mut names: Vec<String> = algorithm_registry()
+        .read()
+        .unwrap_or_else(|err| err.into_inner())
+        .keys()
+        .cloned()
+        .collect();
+    names.sort();
+    names
+}
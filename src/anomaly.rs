@@ -0,0 +1,109 @@
+use crate::{ContentType, Event, VerifyStatus};
+use std::fmt;
+
+// 空文件或全零文件至少有这么多个, 并且占比达到 1/MIN_SHARE 时才报告
+const MIN_FILES: usize = 10;
+const MIN_SHARE: usize = 20;
+
+/// 整个目录树范围内的可疑情况, 例如大量空文件或全零文件
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[non_exhaustive]
+pub enum Anomaly {
+    ManyEmpty { files: usize, total: usize },
+    ManyZeroFilled { files: usize, total: usize },
+    WipedOnCheck { files: usize },
+}
+
+impl Anomaly {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Anomaly::ManyEmpty { .. } => "many_empty",
+            Anomaly::ManyZeroFilled { .. } => "many_zero_filled",
+            Anomaly::WipedOnCheck { .. } => "wiped_on_check",
+        }
+    }
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Anomaly::ManyEmpty { files, total } => write!(
+                f,
+                "{} 个文件中有 {} 个是空文件, 可能是复制失败",
+                total, files
+            ),
+            Anomaly::ManyZeroFilled { files, total } => write!(
+                f,
+                "{} 个文件中有 {} 个开头全为零, 可能是复制失败或文件系统损坏",
+                total, files
+            ),
+            Anomaly::WipedOnCheck { files } => write!(
+                f,
+                "{} 个校验失败的文件现在为空或开头全为零, 可能是复制失败或文件系统损坏",
+                files
+            ),
+        }
+    }
+}
+
+/// 根据哈希和校验事件统计可疑的文件内容
+#[derive(Default)]
+pub struct AnomalyDetector {
+    total: usize,
+    empty: usize,
+    zero_filled: usize,
+    wiped: usize,
+}
+
+impl AnomalyDetector {
+    pub fn new() -> AnomalyDetector {
+        AnomalyDetector::default()
+    }
+
+    pub fn record(&mut self, event: &Event) {
+        let content_type = match event {
+            Event::Hashed { content_type, .. } => *content_type,
+            Event::Verified {
+                content_type: Some(content_type),
+                status,
+                ..
+            } => {
+                // 校验失败并且内容已经变成空或全零, 是数据被清空的典型迹象
+                if *status == VerifyStatus::Failed
+                    && matches!(content_type, ContentType::Empty | ContentType::Zeros)
+                {
+                    self.wiped += 1;
+                }
+                *content_type
+            }
+            _ => return,
+        };
+        self.total += 1;
+        match content_type {
+            ContentType::Empty => self.empty += 1,
+            ContentType::Zeros => self.zero_filled += 1,
+            _ => {}
+        }
+    }
+
+    pub fn findings(&self) -> Vec<Anomaly> {
+        let suspicious = |files: usize| files >= MIN_FILES && files * MIN_SHARE >= self.total;
+        let mut findings = Vec::new();
+        if suspicious(self.empty) {
+            findings.push(Anomaly::ManyEmpty {
+                files: self.empty,
+                total: self.total,
+            });
+        }
+        if suspicious(self.zero_filled) {
+            findings.push(Anomaly::ManyZeroFilled {
+                files: self.zero_filled,
+                total: self.total,
+            });
+        }
+        if self.wiped > 0 {
+            findings.push(Anomaly::WipedOnCheck { files: self.wiped });
+        }
+        findings
+    }
+}
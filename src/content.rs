@@ -0,0 +1,126 @@
+// 识别内容类型时读取的文件开头字节数
+pub(crate) const SNIFF_LEN: usize = 4096;
+
+/// 按文件开头的字节识别出的内容类型
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[non_exhaustive]
+pub enum ContentType {
+    Empty,
+    Zeros,
+    Text,
+    Png,
+    Jpeg,
+    Gif,
+    Tiff,
+    Pdf,
+    Zip,
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+    SevenZip,
+    Rar,
+    Mp4,
+    Matroska,
+    Riff,
+    Mp3,
+    Flac,
+    Ogg,
+    Sqlite,
+    Elf,
+    Exe,
+    Binary,
+}
+
+impl ContentType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ContentType::Empty => "empty",
+            ContentType::Zeros => "zeros",
+            ContentType::Text => "text",
+            ContentType::Png => "png",
+            ContentType::Jpeg => "jpeg",
+            ContentType::Gif => "gif",
+            ContentType::Tiff => "tiff",
+            ContentType::Pdf => "pdf",
+            ContentType::Zip => "zip",
+            ContentType::Gzip => "gzip",
+            ContentType::Bzip2 => "bzip2",
+            ContentType::Xz => "xz",
+            ContentType::Zstd => "zstd",
+            ContentType::SevenZip => "7z",
+            ContentType::Rar => "rar",
+            ContentType::Mp4 => "mp4",
+            ContentType::Matroska => "matroska",
+            ContentType::Riff => "riff",
+            ContentType::Mp3 => "mp3",
+            ContentType::Flac => "flac",
+            ContentType::Ogg => "ogg",
+            ContentType::Sqlite => "sqlite",
+            ContentType::Elf => "elf",
+            ContentType::Exe => "exe",
+            ContentType::Binary => "binary",
+        }
+    }
+}
+
+const MAGIC: &[(&[u8], ContentType)] = &[
+    (b"\x89PNG\r\n\x1a\n", ContentType::Png),
+    (b"\xff\xd8\xff", ContentType::Jpeg),
+    (b"GIF87a", ContentType::Gif),
+    (b"GIF89a", ContentType::Gif),
+    (b"II*\x00", ContentType::Tiff),
+    (b"MM\x00*", ContentType::Tiff),
+    (b"%PDF-", ContentType::Pdf),
+    (b"PK\x03\x04", ContentType::Zip),
+    (b"PK\x05\x06", ContentType::Zip),
+    (b"\x1f\x8b", ContentType::Gzip),
+    (b"BZh", ContentType::Bzip2),
+    (b"\xfd7zXZ\x00", ContentType::Xz),
+    (b"\x28\xb5\x2f\xfd", ContentType::Zstd),
+    (b"7z\xbc\xaf\x27\x1c", ContentType::SevenZip),
+    (b"Rar!\x1a\x07", ContentType::Rar),
+    (b"\x1a\x45\xdf\xa3", ContentType::Matroska),
+    (b"RIFF", ContentType::Riff),
+    (b"ID3", ContentType::Mp3),
+    (b"fLaC", ContentType::Flac),
+    (b"OggS", ContentType::Ogg),
+    (b"SQLite format 3\x00", ContentType::Sqlite),
+    (b"\x7fELF", ContentType::Elf),
+    (b"MZ", ContentType::Exe),
+];
+
+// 根据文件开头的字节判断内容类型
+pub(crate) fn detect(prefix: &[u8]) -> ContentType {
+    if prefix.is_empty() {
+        return ContentType::Empty;
+    }
+    if prefix.iter().all(|&b| b == 0) {
+        return ContentType::Zeros;
+    }
+    if let Some((_, content_type)) = MAGIC.iter().find(|(magic, _)| prefix.starts_with(magic)) {
+        return *content_type;
+    }
+    // MP4 和 MOV 的类型标记在第4个字节之后
+    if prefix.len() >= 8 && &prefix[4..8] == b"ftyp" {
+        return ContentType::Mp4;
+    }
+    if is_text(prefix) {
+        return ContentType::Text;
+    }
+    ContentType::Binary
+}
+
+// 开头是合法的UTF-8并且没有除空白外的控制字符时视为文本, 允许末尾截断半个字符
+fn is_text(prefix: &[u8]) -> bool {
+    let valid = match std::str::from_utf8(prefix) {
+        Ok(text) => text,
+        Err(err) if err.error_len().is_none() => {
+            std::str::from_utf8(&prefix[..err.valid_up_to()]).unwrap_or_default()
+        }
+        Err(_) => return false,
+    };
+    valid
+        .chars()
+        .all(|c| !c.is_control() || matches!(c, '\t' | '\n' | '\r' | '\x0c'))
+}
@@ -0,0 +1,722 @@
+use crate::{Error, FileMeta, Result, DEFAULT_ALGORITHM};
+use std::borrow::Cow;
+use std::ffi::OsString;
+use std::io::BufRead;
+use std::path::{Component, Path, PathBuf, MAIN_SEPARATOR, MAIN_SEPARATOR_STR};
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+
+// 清单头中记录的格式版本, 没有版本头的清单按旧版格式解析
+// 版本3的修改时间可以带小数部分, 旧版本无法解析
+pub(crate) const MANIFEST_VERSION: u32 = 3;
+
+/// 清单的格式: 本程序的格式, xxhsum 的 GNU 格式或 BSD 格式
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[non_exhaustive]
+pub enum ManifestFormat {
+    Native,
+    Xxhsum,
+    Bsd,
+}
+
+impl ManifestFormat {
+    pub fn from_name(name: &str) -> Result<ManifestFormat> {
+        match name {
+            "native" => Ok(ManifestFormat::Native),
+            "xxhsum" => Ok(ManifestFormat::Xxhsum),
+            "bsd" => Ok(ManifestFormat::Bsd),
+            _ => Err(Error::UnsupportedFormat(name.to_string())),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ManifestFormat::Native => "native",
+            ManifestFormat::Xxhsum => "xxhsum",
+            ManifestFormat::Bsd => "bsd",
+        }
+    }
+}
+
+/// 写入清单时如何处理不在清单文件夹中的条目, 例如经符号链接或多个根目录加入的文件
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[non_exhaustive]
+pub enum OutsideRoot {
+    #[default]
+    Absolute,
+    Skip,
+    Fail,
+}
+
+impl OutsideRoot {
+    pub fn from_name(name: &str) -> Result<OutsideRoot> {
+        match name {
+            "absolute" => Ok(OutsideRoot::Absolute),
+            "skip" => Ok(OutsideRoot::Skip),
+            "error" => Ok(OutsideRoot::Fail),
+            _ => Err(Error::UnsupportedOutsideRoot(name.to_string())),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutsideRoot::Absolute => "absolute",
+            OutsideRoot::Skip => "skip",
+            OutsideRoot::Fail => "error",
+        }
+    }
+}
+
+pub(crate) enum ManifestLine<'a> {
+    Header {
+        key: &'a str,
+        value: &'a str,
+    },
+    Entry {
+        format: ManifestFormat,
+        algorithm: Option<&'static str>,
+        path: PathBuf,
+        hash: &'a str,
+        meta: Option<FileMeta>,
+    },
+}
+
+// 按行读取原始字节并去掉行尾的 \n 或 \r\n
+// 不能解码为UTF-8的行返回错误, 而不是像 lines() 那样在这一行静默结束
+pub(crate) fn read_lines(reader: impl BufRead) -> impl Iterator<Item = Result<String>> {
+    reader.split(b'\n').enumerate().map(|(index, line)| {
+        let mut line = line.map_err(Error::Read)?;
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        String::from_utf8(line).map_err(|_| Error::InvalidEncoding { line: index + 1 })
+    })
+}
+
+// 按[路径 | 哈希]、xxhsum和BSD三种格式依次尝试解析一行
+pub(crate) fn parse_line(line: &str, version: u32) -> Result<Option<ManifestLine<'_>>> {
+    if let Some(header) = line.strip_prefix("# ") {
+        return Ok(header
+            .split_once(": ")
+            .map(|(key, value)| ManifestLine::Header {
+                key,
+                value: value.trim(),
+            }));
+    }
+
+    if line.starts_with('[') {
+        if version >= 2 {
+            return parse_native_line(line);
+        }
+        let parts: Vec<&str> = line
+            .trim_matches(|c| c == '[' || c == ']' || c == ' ')
+            .split(" | ")
+            .collect();
+        if parts.len() != 2 && parts.len() != 4 {
+            return Ok(None);
+        }
+        let meta = if parts.len() == 4 {
+            match parse_meta(parts[2], parts[3]) {
+                Some(meta) => Some(meta),
+                None => {
+                    return Err(Error::InvalidMeta {
+                        path: parts[0].to_string(),
+                    })
+                }
+            }
+        } else {
+            None
+        };
+        return Ok(Some(ManifestLine::Entry {
+            format: ManifestFormat::Native,
+            algorithm: None,
+            path: legacy_path(parts[0]),
+            hash: parts[1],
+            meta,
+        }));
+    }
+
+    // GNU 格式中以反斜杠开头的行表示路径经过转义
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(line) => (true, line),
+        None => (false, line),
+    };
+    let decode = |path: &str| {
+        if escaped {
+            decode_path(path)
+        } else {
+            Ok(legacy_path(path))
+        }
+    };
+
+    if let Some((tag, rest)) = line.split_once(" (") {
+        if let Some((path, hash)) = rest.rsplit_once(") = ") {
+            if let Some(algorithm) = bsd_algorithm(tag) {
+                return Ok(Some(ManifestLine::Entry {
+                    format: ManifestFormat::Bsd,
+                    algorithm: Some(algorithm),
+                    path: decode(path)?,
+                    hash,
+                    meta: None,
+                }));
+            }
+        }
+    }
+
+    if let Some((hash, path)) = line.split_once("  ") {
+        if let Some((algorithm, hash)) = xxhsum_algorithm(hash) {
+            return Ok(Some(ManifestLine::Entry {
+                format: ManifestFormat::Xxhsum,
+                algorithm: Some(algorithm),
+                path: decode(path)?,
+                hash,
+                meta: None,
+            }));
+        }
+    }
+
+    Ok(None)
+}
+
+// 第2版的路径经过转义, 只有未转义的 " | " 才是字段分隔符
+fn parse_native_line(line: &str) -> Result<Option<ManifestLine<'_>>> {
+    let inner = match line
+        .strip_prefix('[')
+        .and_then(|line| line.strip_suffix(']'))
+    {
+        Some(inner) => inner,
+        None => return Ok(None),
+    };
+    let parts = split_fields(inner);
+    if parts.len() != 2 && parts.len() != 4 {
+        return Ok(None);
+    }
+    let meta = if parts.len() == 4 {
+        match parse_meta(parts[2], parts[3]) {
+            Some(meta) => Some(meta),
+            None => {
+                return Err(Error::InvalidMeta {
+                    path: parts[0].to_string(),
+                })
+            }
+        }
+    } else {
+        None
+    };
+    Ok(Some(ManifestLine::Entry {
+        format: ManifestFormat::Native,
+        algorithm: None,
+        path: decode_path(parts[0])?,
+        hash: parts[1],
+        meta,
+    }))
+}
+
+fn split_fields(inner: &str) -> Vec<&str> {
+    let mut fields = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in inner.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '|' {
+            let field = &inner[start..i];
+            fields.push(field.strip_suffix(' ').unwrap_or(field));
+            start = i + 1;
+            if inner[start..].starts_with(' ') {
+                start += 1;
+            }
+        }
+    }
+    fields.push(&inner[start..]);
+    fields
+}
+
+pub(crate) fn format_line(
+    format: ManifestFormat,
+    algorithm: &str,
+    path: &Path,
+    hash: &str,
+    meta: Option<FileMeta>,
+) -> Result<String> {
+    // GNU 格式只转义反斜杠和换行, 并在行首加反斜杠标记
+    let (gnu_path, escaped) = encode_gnu_path(path);
+    let prefix = if escaped { "\\" } else { "" };
+    match format {
+        ManifestFormat::Native => {
+            let path = encode_path(path, &['|', '[', ']']);
+            Ok(match meta {
+                Some(meta) => format!(
+                    "[{} | {} | {} | {}]",
+                    path,
+                    hash,
+                    meta.size,
+                    format_mtime(meta)
+                ),
+                None => format!("[{} | {}]", path, hash),
+            })
+        }
+        ManifestFormat::Xxhsum => match algorithm {
+            DEFAULT_ALGORITHM | "xxh64" | "xxh32" => {
+                Ok(format!("{}{}  {}", prefix, hash, gnu_path))
+            }
+            "xxh3-64" => Ok(format!("{}XXH3_{}  {}", prefix, hash, gnu_path)),
+            _ => Err(Error::IncompatibleFormat {
+                format,
+                algorithm: algorithm.to_string(),
+            }),
+        },
+        ManifestFormat::Bsd => match bsd_tag(algorithm) {
+            Some(tag) => Ok(format!("{}{} ({}) = {}", prefix, tag, gnu_path, hash)),
+            None => Err(Error::IncompatibleFormat {
+                format,
+                algorithm: algorithm.to_string(),
+            }),
+        },
+    }
+}
+
+// macOS 返回NFD形式的文件名, 统一转换为NFC以便跨平台比较
+pub(crate) fn normalize_path(path: &Path) -> Cow<'_, Path> {
+    match path.to_str() {
+        Some(path_str) if !is_nfc(path_str) => {
+            Cow::Owned(PathBuf::from(path_str.nfc().collect::<String>()))
+        }
+        _ => Cow::Borrowed(path),
+    }
+}
+
+pub(crate) fn encode_gnu_path(path: &Path) -> (String, bool) {
+    let path = encode_path(path, &[]);
+    let escaped = path.contains('\\');
+    (path, escaped)
+}
+
+// 路径分隔符统一写成 /, 无法按UTF-8解码的字节写成 \xHH
+fn encode_path(path: &Path, special: &[char]) -> String {
+    let mut encoded = String::new();
+    for component in path.components() {
+        // 绝对路径的根目录也写成 /, 它与后面的组件之间不再加分隔符
+        if component == Component::RootDir {
+            encoded.push('/');
+            continue;
+        }
+        if !encoded.is_empty() && !encoded.ends_with('/') {
+            encoded.push('/');
+        }
+        for chunk in component.as_os_str().as_encoded_bytes().utf8_chunks() {
+            for c in chunk.valid().chars() {
+                match c {
+                    '\n' => encoded.push_str("\\n"),
+                    '\r' => encoded.push_str("\\r"),
+                    c if c == '\\' || special.contains(&c) => {
+                        encoded.push('\\');
+                        encoded.push(c);
+                    }
+                    c => encoded.push(c),
+                }
+            }
+            for byte in chunk.invalid() {
+                encoded.push_str(&format!("\\x{:02x}", byte));
+            }
+        }
+    }
+    encoded
+}
+
+fn decode_path(path: &str) -> Result<PathBuf> {
+    let invalid = || Error::InvalidPath {
+        value: path.to_string(),
+    };
+    let (mut decoded, rest) = split_root(path);
+    let mut bytes = Vec::new();
+    let mut chars = rest.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('n') => bytes.push(b'\n'),
+            Some('r') => bytes.push(b'\r'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                if hex.len() != 2 {
+                    return Err(invalid());
+                }
+                bytes.push(u8::from_str_radix(&hex, 16).map_err(|_| invalid())?);
+            }
+            Some(c @ ('\\' | '|' | '[' | ']')) => bytes.push(c as u8),
+            _ => return Err(invalid()),
+        }
+    }
+
+    for component in bytes.split(|&b| b == b'/').filter(|c| !c.is_empty()) {
+        decoded.push(os_string_from_bytes(component.to_vec()));
+    }
+    Ok(decoded)
+}
+
+// 旧版清单直接写入平台路径, \ 和 / 都按分隔符处理, 使Windows生成的清单能在Linux上校验
+fn legacy_path(path: &str) -> PathBuf {
+    let (mut decoded, rest) = split_root(path);
+    decoded.extend(
+        rest.split(['/', '\\'])
+            .filter(|component| !component.is_empty()),
+    );
+    decoded
+}
+
+// 以 / 开头或带盘符的条目是其他工具写入的绝对路径, 需要保留根目录
+fn split_root(path: &str) -> (PathBuf, &str) {
+    let bytes = path.as_bytes();
+    if bytes.len() >= 3
+        && bytes[0].is_ascii_alphabetic()
+        && bytes[1] == b':'
+        && matches!(bytes[2], b'/' | b'\\')
+    {
+        return (
+            PathBuf::from(format!("{}{}", &path[..2], MAIN_SEPARATOR)),
+            &path[3..],
+        );
+    }
+    match path.strip_prefix('/') {
+        Some(rest) => (PathBuf::from(MAIN_SEPARATOR_STR), rest),
+        None => (PathBuf::new(), path),
+    }
+}
+
+// 在Linux上盘符路径不算绝对路径, 需要单独判断
+pub(crate) fn is_absolute_entry(path: &Path) -> bool {
+    path.has_root()
+        || path
+            .to_str()
+            .is_some_and(|path| !split_root(path).0.as_os_str().is_empty())
+}
+
+#[cfg(unix)]
+fn os_string_from_bytes(bytes: Vec<u8>) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+    OsString::from_vec(bytes)
+}
+
+#[cfg(not(unix))]
+fn os_string_from_bytes(bytes: Vec<u8>) -> OsString {
+    String::from_utf8_lossy(&bytes).into_owned().into()
+}
+
+fn bsd_tag(algorithm: &str) -> Option<&'static str> {
+    match algorithm {
+        DEFAULT_ALGORITHM => Some("XXH128"),
+        "xxh3-64" => Some("XXH3"),
+        "xxh64" => Some("XXH64"),
+        "xxh32" => Some("XXH32"),
+        "blake3" => Some("BLAKE3"),
+        _ => None,
+    }
+}
+
+// rclone hashsum 使用的哈希类型名称, md5, sha1, crc32 和 sha256 与已注册的算法同名
+// dropbox, quickxor 等其他类型按已注册的算法名称处理, 没有注册时报告不支持
+pub(crate) fn rclone_algorithm(hash_type: &str) -> &str {
+    match hash_type {
+        "xxh128" => DEFAULT_ALGORITHM,
+        "xxh3" => "xxh3-64",
+        _ => hash_type,
+    }
+}
+
+fn bsd_algorithm(tag: &str) -> Option<&'static str> {
+    match tag {
+        "XXH128" => Some(DEFAULT_ALGORITHM),
+        "XXH3" => Some("xxh3-64"),
+        "XXH64" => Some("xxh64"),
+        "XXH32" => Some("xxh32"),
+        "BLAKE3" => Some("blake3"),
+        _ => None,
+    }
+}
+
+// xxhsum 的 GNU 格式不记录算法, 只能根据哈希前缀和长度推断
+fn xxhsum_algorithm(hash: &str) -> Option<(&'static str, &str)> {
+    let (prefixed, hash) = match hash.strip_prefix("XXH3_") {
+        Some(hash) => (true, hash),
+        None => (false, hash),
+    };
+    if !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    match (prefixed, hash.len()) {
+        (true, 16) => Some(("xxh3-64", hash)),
+        (false, 32) => Some((DEFAULT_ALGORITHM, hash)),
+        (false, 16) => Some(("xxh64", hash)),
+        (false, 8) => Some(("xxh32", hash)),
+        _ => None,
+    }
+}
+
+// 修改时间记录为UTC的Unix纪元以来的秒数, 有纳秒部分时写成固定9位小数
+// 整数秒的时间与旧版本写法相同
+fn format_mtime(meta: FileMeta) -> String {
+    if meta.mtime_nanos == 0 {
+        meta.mtime.to_string()
+    } else {
+        format!("{}.{:09}", meta.mtime, meta.mtime_nanos)
+    }
+}
+
+fn parse_meta(size: &str, mtime: &str) -> Option<FileMeta> {
+    let (secs, nanos) = match mtime.split_once('.') {
+        Some((secs, fraction))
+            if (1..=9).contains(&fraction.len())
+                && fraction.bytes().all(|c| c.is_ascii_digit()) =>
+        {
+            // 少于9位的小数按右侧补零处理
+            let nanos: u32 = fraction.parse().ok()?;
+            (secs, nanos * 10u32.pow(9 - fraction.len() as u32))
+        }
+        Some(_) => return None,
+        None => (mtime, 0),
+    };
+    Some(FileMeta {
+        size: size.parse().ok()?,
+        mtime: secs.parse().ok()?,
+        mtime_nanos: nanos,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH_128: &str = "eefac9d87100cd1336b2e733a5484425";
+    const HASH_64: &str = "0123456789abcdef";
+    const HASH_32: &str = "89abcdef";
+
+    fn entry(
+        line: &str,
+        version: u32,
+    ) -> (
+        ManifestFormat,
+        Option<String>,
+        PathBuf,
+        String,
+        Option<FileMeta>,
+    ) {
+        match parse_line(line, version) {
+            Ok(Some(ManifestLine::Entry {
+                format,
+                algorithm,
+                path,
+                hash,
+                meta,
+            })) => (
+                format,
+                algorithm.map(str::to_string),
+                path,
+                hash.to_string(),
+                meta,
+            ),
+            _ => panic!("无法解析[{}]", line),
+        }
+    }
+
+    // 写出一行再按当前版本读回, 路径, 哈希和元数据都应保持不变
+    fn round_trip(format: ManifestFormat, algorithm: &str, path: &Path, meta: Option<FileMeta>) {
+        let hash = match algorithm {
+            "xxh3-64" | "xxh64" => HASH_64,
+            "xxh32" => HASH_32,
+            _ => HASH_128,
+        };
+        let line = format_line(format, algorithm, path, hash, meta).unwrap();
+        let (parsed_format, parsed_algorithm, parsed_path, parsed_hash, parsed_meta) =
+            entry(&line, MANIFEST_VERSION);
+        assert_eq!(parsed_format, format, "{}", line);
+        if format != ManifestFormat::Native {
+            assert_eq!(parsed_algorithm.as_deref(), Some(algorithm), "{}", line);
+        }
+        assert_eq!(parsed_path, path, "{}", line);
+        assert_eq!(parsed_hash, hash, "{}", line);
+        assert_eq!(
+            parsed_meta.map(|meta| (meta.size, meta.mtime, meta.mtime_nanos)),
+            meta.map(|meta| (meta.size, meta.mtime, meta.mtime_nanos)),
+            "{}",
+            line
+        );
+    }
+
+    fn special_paths() -> Vec<PathBuf> {
+        let mut paths = vec![
+            PathBuf::from("plain.txt"),
+            PathBuf::from("dir/with space/file"),
+            PathBuf::from("pipe | [brackets].txt"),
+            PathBuf::from("back\\slash"),
+            PathBuf::from("new\nline\r.txt"),
+            PathBuf::from("ünïcödé/你好.txt"),
+        ];
+        if cfg!(unix) {
+            paths.push(PathBuf::from(os_string_from_bytes(
+                b"bad\xff\xfe.bin".to_vec(),
+            )));
+        }
+        paths
+    }
+
+    #[test]
+    fn native_lines_round_trip() {
+        for path in special_paths() {
+            round_trip(ManifestFormat::Native, DEFAULT_ALGORITHM, &path, None);
+            round_trip(
+                ManifestFormat::Native,
+                DEFAULT_ALGORITHM,
+                &path,
+                Some(FileMeta::new(12, 1700000000, 0)),
+            );
+            round_trip(
+                ManifestFormat::Native,
+                DEFAULT_ALGORITHM,
+                &path,
+                Some(FileMeta::new(0, 1700000000, 123456789)),
+            );
+        }
+    }
+
+    #[test]
+    fn xxhsum_lines_round_trip() {
+        for algorithm in [DEFAULT_ALGORITHM, "xxh3-64", "xxh64", "xxh32"] {
+            for path in special_paths() {
+                round_trip(ManifestFormat::Xxhsum, algorithm, &path, None);
+            }
+        }
+        let line = format_line(
+            ManifestFormat::Xxhsum,
+            "xxh3-64",
+            Path::new("a"),
+            HASH_64,
+            None,
+        )
+        .unwrap();
+        assert_eq!(line, format!("XXH3_{}  a", HASH_64));
+        assert!(format_line(
+            ManifestFormat::Xxhsum,
+            "blake3",
+            Path::new("a"),
+            HASH_128,
+            None
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn bsd_lines_round_trip() {
+        for algorithm in [DEFAULT_ALGORITHM, "xxh3-64", "xxh64", "xxh32", "blake3"] {
+            for path in special_paths() {
+                round_trip(ManifestFormat::Bsd, algorithm, &path, None);
+            }
+        }
+        let line = format_line(
+            ManifestFormat::Bsd,
+            "xxh64",
+            Path::new("a b"),
+            HASH_64,
+            None,
+        )
+        .unwrap();
+        assert_eq!(line, format!("XXH64 (a b) = {}", HASH_64));
+        assert!(format_line(ManifestFormat::Bsd, "md5", Path::new("a"), HASH_128, None).is_err());
+    }
+
+    #[test]
+    fn gnu_paths_are_escaped_with_a_leading_backslash() {
+        let line = format_line(
+            ManifestFormat::Xxhsum,
+            DEFAULT_ALGORITHM,
+            Path::new("a\nb\\c"),
+            HASH_128,
+            None,
+        )
+        .unwrap();
+        assert_eq!(line, format!("\\{}  a\\nb\\\\c", HASH_128));
+        // 没有转义标记的行中反斜杠是旧版的路径分隔符
+        let (_, _, path, _, _) = entry(&format!("{}  dir\\file", HASH_128), MANIFEST_VERSION);
+        assert_eq!(path, Path::new("dir/file"));
+    }
+
+    #[test]
+    fn native_paths_escape_field_separators() {
+        let line = format_line(
+            ManifestFormat::Native,
+            DEFAULT_ALGORITHM,
+            Path::new("a | b"),
+            HASH_128,
+            None,
+        )
+        .unwrap();
+        assert_eq!(line, format!("[a \\| b | {}]", HASH_128));
+        assert!(parse_line("[bad\\q | 00]", MANIFEST_VERSION).is_err());
+        assert!(parse_line("[bad\\x4 | 00]", MANIFEST_VERSION).is_err());
+    }
+
+    #[test]
+    fn older_versions_still_parse() {
+        // 第1版不转义路径, \ 和 / 都是分隔符, 修改时间只有整秒
+        let (format, _, path, hash, meta) = entry(
+            &format!("[dir\\sub/file | {} | 12 | 1700000000]", HASH_128),
+            1,
+        );
+        assert_eq!(format, ManifestFormat::Native);
+        assert_eq!(path, Path::new("dir/sub/file"));
+        assert_eq!(hash, HASH_128);
+        let meta = meta.unwrap();
+        assert_eq!(
+            (meta.size, meta.mtime, meta.mtime_nanos),
+            (12, 1700000000, 0)
+        );
+
+        // 第2版的路径经过转义
+        let (_, _, path, _, meta) =
+            entry(&format!("[a\\|b\\\\c | {} | 3 | 1700000000]", HASH_128), 2);
+        assert_eq!(path, Path::new("a|b\\c"));
+        assert_eq!(meta.unwrap().mtime, 1700000000);
+
+        // 第3版的修改时间可以带小数, 不足9位时右侧补零
+        let (_, _, _, _, meta) = entry(&format!("[a | {} | 3 | 1700000000.5]", HASH_128), 3);
+        assert_eq!(meta.unwrap().mtime_nanos, 500000000);
+        assert!(parse_line(&format!("[a | {} | 3 | 1700000000.]", HASH_128), 3).is_err());
+    }
+
+    #[test]
+    fn headers_are_recognized() {
+        match parse_line("# algorithm: xxh64 ", MANIFEST_VERSION) {
+            Ok(Some(ManifestLine::Header { key, value })) => {
+                assert_eq!((key, value), ("algorithm", "xxh64"));
+            }
+            _ => panic!("无法解析清单头"),
+        }
+        assert!(matches!(
+            parse_line("# comment", MANIFEST_VERSION),
+            Ok(None)
+        ));
+        assert!(matches!(parse_line("garbage", MANIFEST_VERSION), Ok(None)));
+    }
+
+    #[test]
+    fn absolute_entries_keep_their_root() {
+        let (_, _, path, _, _) = entry(&format!("[/abs/file | {}]", HASH_128), MANIFEST_VERSION);
+        assert!(is_absolute_entry(&path));
+        assert_eq!(path, Path::new(MAIN_SEPARATOR_STR).join("abs").join("file"));
+        let (_, _, path, _, _) = entry(&format!("[C:/dir/file | {}]", HASH_128), MANIFEST_VERSION);
+        assert!(is_absolute_entry(&path));
+        assert!(!is_absolute_entry(Path::new("dir/file")));
+    }
+
+    #[test]
+    fn read_lines_strips_line_endings_and_reports_bad_lines() {
+        let lines: Vec<_> = read_lines(&b"a\r\nb\n\xff\nc"[..]).collect();
+        assert_eq!(lines[0].as_deref().unwrap(), "a");
+        assert_eq!(lines[1].as_deref().unwrap(), "b");
+        assert!(matches!(lines[2], Err(Error::InvalidEncoding { line: 3 })));
+        assert_eq!(lines[3].as_deref().unwrap(), "c");
+    }
+}
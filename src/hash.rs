@@ -0,0 +1,1253 @@
+use crate::content::{self, SNIFF_LEN};
+use crate::format::normalize_path;
+use crate::memory::MemoryGuard;
+use crate::walk::{matches_pattern, parse_pattern};
+use crate::{
+    create_hasher, CompanionFormat, CompanionHashes, ContentType, Digest, DuplicateSet, Error,
+    Event, FileMeta, HashRecord, Journal, LocalSource, Manifest, Result, Source, StreamingHasher,
+    VerifyReport, VerifyStatus, WalkEvent, WalkOptions, DEFAULT_ALGORITHM, DEFAULT_BUFFER_SIZE,
+};
+use crc32fast::Hasher as Crc32;
+use glob::Pattern;
+use sha2::{Digest as _, Sha256};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::future::Future;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use tokio::io::AsyncReadExt;
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+
+// 达到该大小的本地文件改用阻塞线程读取
+const LARGE_FILE_THRESHOLD: u64 = 64 * 1024 * 1024;
+
+const LARGE_FILE_BUFFER_SIZE: usize = 4 * 1024 * 1024;
+
+/// 计算附加哈希的文件范围, 例如只对光盘镜像和大文件额外计算SHA-256
+/// 路径匹配任一模式或大小不小于下限的文件都在范围内
+#[derive(Clone, Default)]
+pub struct CompanionPolicy {
+    patterns: Vec<Pattern>,
+    min_size: Option<u64>,
+}
+
+impl CompanionPolicy {
+    pub fn new() -> CompanionPolicy {
+        CompanionPolicy::default()
+    }
+
+    pub fn pattern(mut self, pattern: &str) -> Result<CompanionPolicy> {
+        self.patterns.push(parse_pattern(pattern)?);
+        Ok(self)
+    }
+
+    pub fn min_size(mut self, bytes: u64) -> CompanionPolicy {
+        self.min_size = Some(bytes);
+        self
+    }
+
+    pub fn matches(&self, relative_path: &Path, size: u64) -> bool {
+        self.min_size.is_some_and(|min_size| size >= min_size)
+            || self
+                .patterns
+                .iter()
+                .any(|pattern| matches_pattern(pattern, relative_path))
+    }
+}
+
+/// 计算目录中文件的哈希并生成或更新清单, 通过构建方法设置选项
+pub struct HashGenerator {
+    jobs: usize,
+    buffer_size: usize,
+    min_free_memory: Option<u64>,
+    max_rss: Option<u64>,
+    walk_options: WalkOptions,
+    algorithm: String,
+    companion_formats: Vec<CompanionFormat>,
+    companion_policy: Option<CompanionPolicy>,
+    mtime_tolerance: u64,
+    size_only: bool,
+    journal: Option<Arc<Journal>>,
+}
+
+impl Default for HashGenerator {
+    fn default() -> Self {
+        HashGenerator {
+            jobs: 16,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            min_free_memory: None,
+            max_rss: None,
+            walk_options: WalkOptions::default(),
+            algorithm: DEFAULT_ALGORITHM.to_string(),
+            companion_formats: Vec::new(),
+            companion_policy: None,
+            mtime_tolerance: 0,
+            size_only: false,
+            journal: None,
+        }
+    }
+}
+
+impl HashGenerator {
+    pub fn new() -> HashGenerator {
+        HashGenerator::default()
+    }
+
+    pub fn jobs(mut self, jobs: usize) -> HashGenerator {
+        self.jobs = jobs;
+        self
+    }
+
+    pub fn buffer_size(mut self, buffer_size: usize) -> HashGenerator {
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    /// 系统可用内存低于该值时减少同时计算的文件数并改用较小的缓冲区
+    pub fn min_free_memory(mut self, bytes: u64) -> HashGenerator {
+        self.min_free_memory = Some(bytes);
+        self
+    }
+
+    /// 本进程的常驻内存超过该值时同样减少同时计算的文件数并改用较小的缓冲区
+    pub fn max_rss(mut self, bytes: u64) -> HashGenerator {
+        self.max_rss = Some(bytes);
+        self
+    }
+
+    fn pool_options(&self) -> PoolOptions {
+        PoolOptions {
+            jobs: self.jobs,
+            buffer_size: self.buffer_size,
+            min_free_memory: self.min_free_memory,
+            max_rss: self.max_rss,
+        }
+    }
+
+    pub fn walk_options(mut self, walk_options: WalkOptions) -> HashGenerator {
+        self.walk_options = walk_options;
+        self
+    }
+
+    pub fn algorithm(mut self, algorithm: &str) -> HashGenerator {
+        self.algorithm = algorithm.to_string();
+        self
+    }
+
+    pub fn also_emit(mut self, companion_formats: &[CompanionFormat]) -> HashGenerator {
+        self.companion_formats = companion_formats.to_vec();
+        self
+    }
+
+    /// 只对匹配策略的文件计算附加格式的哈希, 不设置时对所有文件计算
+    pub fn companion_policy(mut self, policy: CompanionPolicy) -> HashGenerator {
+        self.companion_policy = Some(policy);
+        self
+    }
+
+    /// 更新时修改时间相差不超过该秒数的文件视为未改变, 用于时钟有偏差的机器之间复制的文件
+    pub fn mtime_tolerance(mut self, seconds: u64) -> HashGenerator {
+        self.mtime_tolerance = seconds;
+        self
+    }
+
+    /// 更新时只比较文件大小, 不比较修改时间
+    pub fn size_only(mut self, size_only: bool) -> HashGenerator {
+        self.size_only = size_only;
+        self
+    }
+
+    /// 每个新计算出的哈希先追加到日志中
+    pub fn journal(mut self, journal: Arc<Journal>) -> HashGenerator {
+        self.journal = Some(journal);
+        self
+    }
+
+    fn hash_context(&self, source: &Arc<dyn Source>, algorithm: &str) -> HashTaskContext {
+        HashTaskContext::new(
+            source,
+            algorithm,
+            &self.companion_formats,
+            self.companion_policy.as_ref(),
+        )
+    }
+
+    fn is_unchanged(&self, stored: Option<FileMeta>, meta: FileMeta) -> bool {
+        let Some(stored) = stored else {
+            return false;
+        };
+        if stored.size != meta.size {
+            return false;
+        }
+        if self.size_only {
+            return true;
+        }
+        if self.mtime_tolerance > 0 {
+            return stored.mtime.abs_diff(meta.mtime) <= self.mtime_tolerance;
+        }
+        // 旧版本只记录整秒, 没有纳秒部分时只比较秒数
+        stored.mtime == meta.mtime
+            && (stored.mtime_nanos == 0 || stored.mtime_nanos == meta.mtime_nanos)
+    }
+
+    /// 遍历文件夹中的所有文件并生成清单
+    pub async fn run(
+        &self,
+        folder_path: &Path,
+        on_event: impl FnMut(Event<'_>),
+    ) -> Result<Manifest> {
+        self.run_source(Arc::new(LocalSource::new(folder_path)), on_event)
+            .await
+    }
+
+    /// 遍历任意来源中的所有文件并生成清单
+    pub async fn run_source(
+        &self,
+        source: Arc<dyn Source>,
+        mut on_event: impl FnMut(Event<'_>),
+    ) -> Result<Manifest> {
+        let folder_path = source.root();
+
+        // 提前检查算法是否已注册
+        create_hasher(&self.algorithm)?;
+
+        let (tx, mut rx) = mpsc::channel(64);
+        let context = self.hash_context(&source, &self.algorithm);
+        let (job_tx, handles) = context.spawn_workers(self.pool_options(), tx);
+
+        // 在阻塞线程中遍历目录, 找到文件后立即提交给工作任务计算哈希
+        let (walk_tx, mut walk_rx) = mpsc::channel(64);
+        let mut walker = {
+            let source = Arc::clone(&source);
+            let walk_options = self.walk_options.clone();
+            tokio::task::spawn_blocking(move || {
+                let mut stopped = false;
+                source.walk(&walk_options, &mut |event| {
+                    // 出错或工作任务已停止后跳过剩余的文件
+                    if stopped {
+                        return;
+                    }
+                    // 接收端已提前返回时忽略发送错误
+                    match event {
+                        WalkEvent::File(file_path) => match source.metadata(&file_path) {
+                            Ok(meta) => {
+                                let _ = walk_tx
+                                    .blocking_send(Discovered::File(file_path.clone(), meta));
+                                // 任务队列已满时在这里等待, 遍历不会远远领先于哈希计算
+                                stopped = job_tx.blocking_send((file_path, meta)).is_err();
+                            }
+                            Err(err) => {
+                                let _ = walk_tx.blocking_send(Discovered::Failed(err));
+                                stopped = true;
+                            }
+                        },
+                        event => {
+                            let _ = walk_tx.blocking_send(Discovered::Walk(event));
+                        }
+                    }
+                })
+            })
+        };
+
+        let mut file_paths = Vec::new();
+        let mut hash_cache = HashMap::new();
+        let mut walking = true;
+        let mut hashing = true;
+        let mut received = 0;
+
+        while walking || received < file_paths.len() {
+            let mut walk_finished = false;
+            let result = tokio::select! {
+                event = walk_rx.recv(), if walking => match event {
+                    Some(Discovered::File(file_path, meta)) => {
+                        on_event(Event::Planned {
+                            files: 1,
+                            bytes: meta.size,
+                        });
+                        file_paths.push(file_path);
+                        Ok(())
+                    }
+                    Some(Discovered::Walk(event)) => {
+                        forward_walk_event(event, &mut on_event);
+                        Ok(())
+                    }
+                    Some(Discovered::Failed(err)) => Err(err),
+                    None => {
+                        walk_finished = true;
+                        Ok(())
+                    }
+                },
+                // 工作任务全部退出后仍需取完遍历线程发来的消息
+                result = rx.recv(), if hashing => match result {
+                    Some(result) => {
+                        received += 1;
+                        receive_hash(
+                            result,
+                            &mut hash_cache,
+                            self.journal.as_deref(),
+                            &mut on_event,
+                        )
+                    }
+                    None => {
+                        hashing = false;
+                        Ok(())
+                    }
+                },
+                else => break,
+            };
+
+            // 遍历线程结束后检查遍历是否出错
+            let result = if walk_finished {
+                walking = false;
+                result.and(
+                    (&mut walker)
+                        .await
+                        .map_err(Error::task)
+                        .and_then(|result| result),
+                )
+            } else {
+                result
+            };
+            if let Err(err) = result {
+                abort_all_async_tasks(&handles);
+                return Err(err);
+            }
+        }
+
+        // 等待所有异步任务完成
+        await_all_async_tasks(handles).await?;
+
+        // 按遍历顺序生成清单
+        let mut manifest = Manifest::new(folder_path, &self.algorithm);
+        for file_path in file_paths {
+            match hash_cache.remove(&file_path) {
+                Some(record) => manifest.insert(file_path, record),
+                None => return Err(Error::MissingHash(file_path)),
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// 只计算指定文件的哈希, 不遍历目录
+    pub async fn run_paths(
+        &self,
+        folder_path: &Path,
+        file_paths: Vec<PathBuf>,
+        on_event: impl FnMut(Event<'_>),
+    ) -> Result<Manifest> {
+        let source = Arc::new(LocalSource::new(folder_path));
+        self.run_paths_source(source, file_paths, on_event).await
+    }
+
+    pub async fn run_paths_source(
+        &self,
+        source: Arc<dyn Source>,
+        file_paths: Vec<PathBuf>,
+        mut on_event: impl FnMut(Event<'_>),
+    ) -> Result<Manifest> {
+        // 提前检查算法是否已注册
+        create_hasher(&self.algorithm)?;
+
+        let mut file_metas = Vec::with_capacity(file_paths.len());
+        for file_path in &file_paths {
+            file_metas.push((file_path.clone(), source.metadata(file_path)?));
+        }
+        let hash_cache = hash_files(
+            self.hash_context(&source, &self.algorithm),
+            file_metas,
+            self.pool_options(),
+            self.journal.as_deref(),
+            &mut on_event,
+        )
+        .await?;
+
+        // 按传入顺序生成清单
+        let mut manifest = Manifest::new(source.root(), &self.algorithm);
+        for file_path in file_paths {
+            match hash_cache.get(&file_path) {
+                Some(record) => manifest.insert(file_path, record.clone()),
+                None => return Err(Error::MissingHash(file_path)),
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// 从通道中逐个接收要计算哈希的文件, 所有文件共用一个工作任务池, 通道关闭后返回清单
+    /// 用于调用方边产生路径边计算哈希; 单个文件出错时报告警告并继续处理后续文件
+    pub async fn run_paths_stream(
+        &self,
+        folder_path: &Path,
+        mut file_paths: mpsc::Receiver<PathBuf>,
+        mut on_event: impl FnMut(Event<'_>),
+    ) -> Result<Manifest> {
+        // 提前检查算法是否已注册
+        create_hasher(&self.algorithm)?;
+
+        let source: Arc<dyn Source> = Arc::new(LocalSource::new(folder_path));
+        let (tx, mut rx) = mpsc::channel(64);
+        let context = self.hash_context(&source, &self.algorithm);
+        let (job_tx, handles) = context.spawn_workers(self.pool_options(), tx);
+
+        // 通道关闭后丢弃任务队列的发送端, 工作任务处理完剩余的任务后退出
+        let mut job_tx = Some(job_tx);
+        let mut receiving = true;
+        // 等待任务队列空出位置的文件, 此时不接收新的路径, 但继续接收哈希
+        let mut pending = None;
+        let mut planned = Vec::new();
+        let mut hash_cache = HashMap::new();
+        let mut hashing = true;
+
+        while hashing {
+            let result = tokio::select! {
+                permit = async { job_tx.as_ref().unwrap().reserve().await }, if pending.is_some() => {
+                    if let Ok(permit) = permit {
+                        permit.send(pending.take().unwrap());
+                    }
+                    Ok(())
+                }
+                file_path = file_paths.recv(), if receiving && pending.is_none() => {
+                    match file_path {
+                        Some(file_path) => match source.metadata(&file_path) {
+                            Ok(meta) => {
+                                on_event(Event::Planned {
+                                    files: 1,
+                                    bytes: meta.size,
+                                });
+                                planned.push(file_path.clone());
+                                pending = Some((file_path, meta));
+                            }
+                            Err(err) => on_event(Event::Warning(&err)),
+                        },
+                        None => receiving = false,
+                    }
+                    Ok(())
+                }
+                result = rx.recv() => match result {
+                    Some(Err(err)) => {
+                        on_event(Event::Warning(&err));
+                        Ok(())
+                    }
+                    Some(result) => receive_hash(
+                        result,
+                        &mut hash_cache,
+                        self.journal.as_deref(),
+                        &mut on_event,
+                    ),
+                    None => {
+                        hashing = false;
+                        Ok(())
+                    }
+                },
+            };
+            if let Err(err) = result {
+                abort_all_async_tasks(&handles);
+                return Err(err);
+            }
+            if !receiving && pending.is_none() {
+                job_tx = None;
+            }
+        }
+
+        // 等待所有异步任务完成
+        await_all_async_tasks(handles).await?;
+
+        // 按接收顺序生成清单, 跳过出错的文件
+        let mut manifest = Manifest::new(folder_path, &self.algorithm);
+        for file_path in planned {
+            if let Some(record) = hash_cache.remove(&file_path) {
+                manifest.insert(file_path, record);
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// 只重新计算新增和改变的文件, 其他文件复用清单中的哈希
+    pub async fn update(
+        &self,
+        manifest: &Manifest,
+        on_event: impl FnMut(Event<'_>),
+    ) -> Result<Manifest> {
+        let source = Arc::new(LocalSource::new(manifest.folder_path()));
+        self.update_source(source, manifest, on_event).await
+    }
+
+    pub async fn update_source(
+        &self,
+        source: Arc<dyn Source>,
+        manifest: &Manifest,
+        mut on_event: impl FnMut(Event<'_>),
+    ) -> Result<Manifest> {
+        let folder_path = manifest.folder_path();
+
+        // 获取所有文件路径
+        let file_paths = list_files(&*source, &self.walk_options, &mut on_event)?;
+
+        // 复用未改变文件的哈希, 收集需要重新计算哈希的文件
+        let mut hash_cache = HashMap::new();
+        let mut changed_file_paths = Vec::new();
+        for file_path in &file_paths {
+            let meta = source.metadata(file_path)?;
+            match manifest.get(file_path) {
+                Some(record) if self.is_unchanged(record.meta, meta) => {
+                    // 保留清单中原有的大小和修改时间, 之后的更新仍以原来的修改时间为准比较,
+                    // 容差范围内的偏差不会逐次累积, 只比较大小时也不会覆盖原来的修改时间
+                    hash_cache.insert(file_path.clone(), record.clone());
+                }
+                Some(_) => {
+                    on_event(Event::Changed(file_path));
+                    changed_file_paths.push((file_path.clone(), meta));
+                }
+                None => {
+                    on_event(Event::Added(file_path));
+                    changed_file_paths.push((file_path.clone(), meta));
+                }
+            }
+        }
+
+        // 报告已删除的文件
+        let file_path_set: HashSet<Cow<Path>> = file_paths
+            .iter()
+            .map(|file_path| normalize_path(file_path))
+            .collect();
+        for (file_path, _) in manifest.iter() {
+            if !file_path_set.contains(file_path.as_path()) {
+                on_event(Event::Removed(file_path));
+            }
+        }
+
+        // 重新计算改变文件的哈希
+        hash_cache.extend(
+            hash_files(
+                self.hash_context(&source, manifest.algorithm()),
+                changed_file_paths,
+                self.pool_options(),
+                self.journal.as_deref(),
+                &mut on_event,
+            )
+            .await?,
+        );
+
+        // 按遍历顺序生成清单
+        let mut new_manifest = Manifest::new(folder_path, manifest.algorithm());
+        new_manifest.set_format(manifest.format());
+        new_manifest.set_timestamps(manifest.timestamps());
+        for file_path in file_paths {
+            match hash_cache.remove(&file_path) {
+                Some(record) => new_manifest.insert(file_path, record),
+                None => return Err(Error::MissingHash(file_path)),
+            }
+        }
+        Ok(new_manifest)
+    }
+
+    /// 校验旧清单的同时生成新清单, 每个文件只读取一次
+    pub async fn refresh(
+        &self,
+        manifest: &Manifest,
+        on_event: impl FnMut(Event<'_>),
+    ) -> Result<(Manifest, VerifyReport)> {
+        let source = Arc::new(LocalSource::new(manifest.folder_path()));
+        self.refresh_source(source, manifest, on_event).await
+    }
+
+    /// 哈希不同但大小和修改时间没有变化的文件视为损坏, 新清单中保留旧的哈希,
+    /// 不会把损坏后的内容记录为正确的哈希; 修改时间变化的文件按正常修改记录新的哈希
+    pub async fn refresh_source(
+        &self,
+        source: Arc<dyn Source>,
+        manifest: &Manifest,
+        mut on_event: impl FnMut(Event<'_>),
+    ) -> Result<(Manifest, VerifyReport)> {
+        let folder_path = manifest.folder_path();
+
+        let file_paths = list_files(&*source, &self.walk_options, &mut on_event)?;
+        let mut file_metas = Vec::new();
+        for file_path in &file_paths {
+            file_metas.push((file_path.clone(), source.metadata(file_path)?));
+        }
+        let metas: HashMap<PathBuf, FileMeta> = file_metas.iter().cloned().collect();
+
+        // 计算出哈希后立即与旧清单比较
+        let mut results = Vec::new();
+        let mut corrupted = HashSet::new();
+        let mut journal_error = None;
+        let mut hash_cache = hash_files(
+            self.hash_context(&source, manifest.algorithm()),
+            file_metas,
+            self.pool_options(),
+            None,
+            &mut |event| match event {
+                Event::Hashed {
+                    file_path,
+                    hash,
+                    bytes,
+                    content_type,
+                } => {
+                    let status = match manifest.get(file_path) {
+                        Some(record) if record.hash == *hash => Some(VerifyStatus::Passed),
+                        // 没有记录修改时间时无法区分修改和损坏, 按损坏处理
+                        Some(record)
+                            if record.meta.is_none()
+                                || self.is_unchanged(record.meta, metas[file_path]) =>
+                        {
+                            corrupted.insert(file_path.to_path_buf());
+                            Some(VerifyStatus::Failed)
+                        }
+                        _ => None,
+                    };
+                    // 损坏文件的新哈希不写入日志, 否则中断后再次运行时会把损坏后的内容当作正确的哈希
+                    if let (Some(journal), None) = (self.journal.as_deref(), &journal_error) {
+                        if status != Some(VerifyStatus::Failed) {
+                            let record = HashRecord {
+                                hash: hash.clone(),
+                                meta: Some(metas[file_path]),
+                                companions: CompanionHashes::default(),
+                            };
+                            journal_error = journal.append(file_path, &record).err();
+                        }
+                    }
+                    let status = match status {
+                        Some(status) => status,
+                        None if manifest.get(file_path).is_some() => {
+                            on_event(Event::Changed(file_path));
+                            return;
+                        }
+                        None => {
+                            on_event(Event::Added(file_path));
+                            return;
+                        }
+                    };
+                    results.push((file_path.to_path_buf(), status));
+                    on_event(Event::Verified {
+                        file_path,
+                        hash: Some(hash),
+                        bytes,
+                        content_type: Some(content_type),
+                        status,
+                    });
+                }
+                event => on_event(event),
+            },
+        )
+        .await?;
+        if let Some(err) = journal_error {
+            return Err(err);
+        }
+
+        // 旧清单中已不存在的文件
+        let file_path_set: HashSet<Cow<Path>> = file_paths
+            .iter()
+            .map(|file_path| normalize_path(file_path))
+            .collect();
+        for (file_path, _) in manifest.iter() {
+            if !file_path_set.contains(file_path.as_path()) {
+                results.push((file_path.clone(), VerifyStatus::Missing));
+                on_event(Event::Verified {
+                    file_path,
+                    hash: None,
+                    bytes: 0,
+                    content_type: None,
+                    status: VerifyStatus::Missing,
+                });
+            }
+        }
+
+        // 按遍历顺序生成清单
+        let mut new_manifest = Manifest::new(folder_path, manifest.algorithm());
+        new_manifest.set_format(manifest.format());
+        new_manifest.set_timestamps(manifest.timestamps());
+        for file_path in file_paths {
+            let record = match manifest.get(&file_path) {
+                Some(record) if corrupted.contains(&file_path) => {
+                    hash_cache.remove(&file_path);
+                    Some(record.clone())
+                }
+                _ => hash_cache.remove(&file_path),
+            };
+            match record {
+                Some(record) => new_manifest.insert(file_path, record),
+                None => return Err(Error::MissingHash(file_path)),
+            }
+        }
+        Ok((
+            new_manifest,
+            VerifyReport {
+                results,
+                unknown: Vec::new(),
+            },
+        ))
+    }
+
+    pub async fn find_duplicates(
+        &self,
+        folder_path: &Path,
+        on_event: impl FnMut(Event<'_>),
+    ) -> Result<Vec<DuplicateSet>> {
+        self.find_duplicates_source(Arc::new(LocalSource::new(folder_path)), on_event)
+            .await
+    }
+
+    pub async fn find_duplicates_source(
+        &self,
+        source: Arc<dyn Source>,
+        mut on_event: impl FnMut(Event<'_>),
+    ) -> Result<Vec<DuplicateSet>> {
+        // 提前检查算法是否已注册
+        create_hasher(&self.algorithm)?;
+
+        // 获取所有文件路径和元数据
+        let file_paths = list_files(&*source, &self.walk_options, &mut on_event)?;
+        let mut file_metas = Vec::with_capacity(file_paths.len());
+        let mut size_counts = HashMap::new();
+        for file_path in &file_paths {
+            let meta = source.metadata(file_path)?;
+            *size_counts.entry(meta.size).or_insert(0) += 1;
+            file_metas.push((file_path.clone(), meta));
+        }
+
+        // 大小不同的文件不可能重复, 只计算大小相同的文件的哈希
+        file_metas.retain(|(_, meta)| meta.size > 0 && size_counts[&meta.size] > 1);
+        let mut hash_cache = hash_files(
+            HashTaskContext::new(&source, &self.algorithm, &[], None),
+            file_metas,
+            self.pool_options(),
+            None,
+            &mut on_event,
+        )
+        .await?;
+
+        let mut manifest = Manifest::new(source.root(), &self.algorithm);
+        for file_path in file_paths {
+            if let Some(record) = hash_cache.remove(&file_path) {
+                manifest.insert(file_path, record);
+            }
+        }
+        Ok(manifest.duplicates())
+    }
+}
+
+/// 读取文件的大小和修改时间
+pub fn get_file_meta(file_path: &Path) -> Result<FileMeta> {
+    let metadata = fs::metadata(file_path).map_err(|err| Error::io(file_path, err))?;
+    let modified = metadata
+        .modified()
+        .map_err(|err| Error::io(file_path, err))?;
+    // Windows的文件时间精度为100纳秒, 同样能完整保存
+    let (mtime, mtime_nanos) = match modified.duration_since(UNIX_EPOCH) {
+        Ok(duration) => (duration.as_secs(), duration.subsec_nanos()),
+        Err(_) => (0, 0),
+    };
+    Ok(FileMeta {
+        size: metadata.len(),
+        mtime,
+        mtime_nanos,
+    })
+}
+
+/// 计算单个文件的哈希
+pub async fn compute_hash(file_path: &Path, algorithm: &str) -> Result<Digest> {
+    let (hash, _) = compute_hash_with_companions(file_path, algorithm, &[]).await?;
+    Ok(hash)
+}
+
+/// 计算单个文件的哈希, 同时计算附加格式需要的哈希
+pub async fn compute_hash_with_companions(
+    file_path: &Path,
+    algorithm: &str,
+    companion_formats: &[CompanionFormat],
+) -> Result<(Digest, CompanionHashes)> {
+    let output = hash_source_file(
+        &LocalSource::new(file_path.parent().unwrap_or(file_path)),
+        file_path,
+        algorithm,
+        companion_formats,
+        BufferSizes::new(DEFAULT_BUFFER_SIZE),
+    )
+    .await?;
+    Ok((output.hash, output.companions))
+}
+
+pub(crate) struct HashOutput {
+    pub(crate) hash: Digest,
+    pub(crate) companions: CompanionHashes,
+    pub(crate) bytes: u64,
+    pub(crate) content_type: ContentType,
+}
+
+pub(crate) async fn hash_source_file(
+    source: &dyn Source,
+    file_path: &Path,
+    algorithm: &str,
+    companion_formats: &[CompanionFormat],
+    buffers: BufferSizes,
+) -> Result<HashOutput> {
+    // 本地大文件在阻塞线程中用大缓冲区顺序读取, 避免占用异步运行时的线程
+    if let Some(local_path) = source.local_path(file_path) {
+        let is_large = fs::metadata(local_path)
+            .map(|metadata| metadata.len() >= LARGE_FILE_THRESHOLD)
+            .unwrap_or(false);
+        if is_large {
+            let local_path = local_path.to_path_buf();
+            let algorithm = algorithm.to_string();
+            let companion_formats = companion_formats.to_vec();
+            return tokio::task::spawn_blocking(move || {
+                hash_local_file(&local_path, &algorithm, &companion_formats, buffers.large)
+            })
+            .await
+            .map_err(Error::task)?;
+        }
+    }
+
+    let mut hasher = FileHasher::new(algorithm, companion_formats)?;
+    let mut reader = source.open(file_path).await?;
+    let mut buf = vec![0; buffers.normal];
+    loop {
+        let n = reader
+            .read(&mut buf)
+            .await
+            .map_err(|err| Error::io(file_path, err))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finish())
+}
+
+fn hash_local_file(
+    file_path: &Path,
+    algorithm: &str,
+    companion_formats: &[CompanionFormat],
+    buffer_size: usize,
+) -> Result<HashOutput> {
+    let mut hasher = FileHasher::new(algorithm, companion_formats)?;
+    let mut file = File::open(file_path).map_err(|err| Error::io(file_path, err))?;
+    let mut buf = vec![0; buffer_size];
+    loop {
+        let n = file
+            .read(&mut buf)
+            .map_err(|err| Error::io(file_path, err))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finish())
+}
+
+// 同时计算主哈希和附加格式的哈希
+struct FileHasher {
+    hasher: Box<dyn StreamingHasher>,
+    sha256: Option<Sha256>,
+    crc32: Option<Crc32>,
+    bytes: u64,
+    prefix: Vec<u8>,
+}
+
+impl FileHasher {
+    fn new(algorithm: &str, companion_formats: &[CompanionFormat]) -> Result<FileHasher> {
+        Ok(FileHasher {
+            hasher: create_hasher(algorithm)?,
+            sha256: companion_formats
+                .contains(&CompanionFormat::Sha256sum)
+                .then(Sha256::new),
+            crc32: companion_formats
+                .contains(&CompanionFormat::Sfv)
+                .then(Crc32::new),
+            bytes: 0,
+            prefix: Vec::new(),
+        })
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        // 保留文件开头的字节用于识别内容类型
+        if self.prefix.len() < SNIFF_LEN {
+            let n = data.len().min(SNIFF_LEN - self.prefix.len());
+            self.prefix.extend_from_slice(&data[..n]);
+        }
+        self.bytes += data.len() as u64;
+        self.hasher.update(data);
+        if let Some(sha256) = &mut self.sha256 {
+            sha256.update(data);
+        }
+        if let Some(crc32) = &mut self.crc32 {
+            crc32.update(data);
+        }
+    }
+
+    fn finish(self) -> HashOutput {
+        HashOutput {
+            hash: self.hasher.finish(),
+            companions: CompanionHashes {
+                sha256: self.sha256.map(|sha256| sha256.finalize().into()),
+                crc32: self.crc32.map(|crc32| crc32.finalize()),
+            },
+            bytes: self.bytes,
+            content_type: content::detect(&self.prefix),
+        }
+    }
+}
+
+fn list_files(
+    source: &dyn Source,
+    walk_options: &WalkOptions,
+    on_event: &mut impl FnMut(Event<'_>),
+) -> Result<Vec<PathBuf>> {
+    source.list(walk_options, &mut |event| {
+        forward_walk_event(event, on_event)
+    })
+}
+
+fn forward_walk_event(event: WalkEvent, on_event: &mut impl FnMut(Event<'_>)) {
+    match event {
+        WalkEvent::Warning(err) => on_event(Event::Warning(&err)),
+        WalkEvent::Skipped { path, reason } => on_event(Event::Skipped {
+            file_path: &path,
+            reason,
+        }),
+        WalkEvent::File(_) => {}
+    }
+}
+
+async fn hash_files(
+    context: HashTaskContext,
+    file_metas: Vec<(PathBuf, FileMeta)>,
+    pool: PoolOptions,
+    journal: Option<&Journal>,
+    on_event: &mut impl FnMut(Event<'_>),
+) -> Result<HashMap<PathBuf, HashRecord>> {
+    let (tx, mut rx) = mpsc::channel(64);
+    let (job_tx, mut handles) = context.spawn_workers(pool, tx);
+
+    on_event(Event::Planned {
+        files: file_metas.len(),
+        bytes: file_metas.iter().map(|(_, meta)| meta.size).sum(),
+    });
+    handles.push(spawn_feeder(job_tx, file_metas));
+
+    // 从通道接收哈希并把哈希写入哈希缓存
+    let mut hash_cache = HashMap::new();
+    while let Some(result) = rx.recv().await {
+        if let Err(err) = receive_hash(result, &mut hash_cache, journal, on_event) {
+            abort_all_async_tasks(&handles);
+            return Err(err);
+        }
+    }
+
+    // 等待所有异步任务完成
+    await_all_async_tasks(handles).await?;
+
+    Ok(hash_cache)
+}
+
+type HashTaskResult = Result<(PathBuf, (HashRecord, u64, ContentType))>;
+
+// 遍历线程发给接收端的消息
+enum Discovered {
+    File(PathBuf, FileMeta),
+    Walk(WalkEvent),
+    Failed(Error),
+}
+
+// 计算哈希的工作任务共享的参数
+struct HashTaskContext {
+    source: Arc<dyn Source>,
+    algorithm: String,
+    companion_formats: Vec<CompanionFormat>,
+    companion_policy: Option<CompanionPolicy>,
+}
+
+impl HashTaskContext {
+    fn new(
+        source: &Arc<dyn Source>,
+        algorithm: &str,
+        companion_formats: &[CompanionFormat],
+        companion_policy: Option<&CompanionPolicy>,
+    ) -> HashTaskContext {
+        HashTaskContext {
+            source: Arc::clone(source),
+            algorithm: algorithm.to_string(),
+            companion_formats: companion_formats.to_vec(),
+            companion_policy: companion_policy.cloned(),
+        }
+    }
+
+    fn spawn_workers(
+        self,
+        pool: PoolOptions,
+        tx: mpsc::Sender<HashTaskResult>,
+    ) -> (mpsc::Sender<(PathBuf, FileMeta)>, Vec<JoinHandle<()>>) {
+        let context = Arc::new(self);
+        spawn_workers(pool, tx, move |(file_path, meta), buffers| {
+            let context = Arc::clone(&context);
+            async move { context.hash(file_path, meta, buffers).await }
+        })
+    }
+
+    async fn hash(
+        &self,
+        file_path: PathBuf,
+        meta: FileMeta,
+        buffers: BufferSizes,
+    ) -> HashTaskResult {
+        // 不在策略范围内的文件只计算主算法的哈希
+        let companion_formats = match &self.companion_policy {
+            Some(policy) => {
+                let relative_path = file_path
+                    .strip_prefix(self.source.root())
+                    .unwrap_or(&file_path);
+                if policy.matches(relative_path, meta.size) {
+                    &self.companion_formats[..]
+                } else {
+                    &[]
+                }
+            }
+            None => &self.companion_formats[..],
+        };
+        hash_file(
+            &*self.source,
+            &file_path,
+            meta,
+            &self.algorithm,
+            companion_formats,
+            buffers,
+        )
+        .await
+        .map(|record| (file_path, record))
+    }
+}
+
+// 工作任务池的参数
+#[derive(Clone, Copy)]
+pub(crate) struct PoolOptions {
+    pub(crate) jobs: usize,
+    pub(crate) buffer_size: usize,
+    pub(crate) min_free_memory: Option<u64>,
+    pub(crate) max_rss: Option<u64>,
+}
+
+// 读取文件时使用的缓冲区大小, 本地大文件使用更大的缓冲区
+#[derive(Clone, Copy)]
+pub(crate) struct BufferSizes {
+    normal: usize,
+    large: usize,
+}
+
+impl BufferSizes {
+    fn new(buffer_size: usize) -> BufferSizes {
+        BufferSizes {
+            normal: buffer_size,
+            large: buffer_size.max(LARGE_FILE_BUFFER_SIZE),
+        }
+    }
+
+    fn reduced(self) -> BufferSizes {
+        BufferSizes {
+            large: self.normal,
+            ..self
+        }
+    }
+}
+
+// 固定数量的工作任务从有界队列中取出任务, 内存占用不随文件数量增长
+pub(crate) fn spawn_workers<J, R, F, Fut>(
+    pool: PoolOptions,
+    tx: mpsc::Sender<R>,
+    work: F,
+) -> (mpsc::Sender<J>, Vec<JoinHandle<()>>)
+where
+    J: Send + 'static,
+    R: Send + 'static,
+    F: Fn(J, BufferSizes) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = R> + Send,
+{
+    let jobs = pool.jobs.max(1);
+    let (job_tx, job_rx) = mpsc::channel(jobs * 2);
+    let job_rx = Arc::new(Mutex::new(job_rx));
+    let work = Arc::new(work);
+    let buffers = BufferSizes::new(pool.buffer_size);
+    let guard = (pool.min_free_memory.is_some() || pool.max_rss.is_some())
+        .then(|| MemoryGuard::start(pool.min_free_memory, pool.max_rss, jobs));
+    let handles = (0..jobs)
+        .map(|_| {
+            let job_rx = Arc::clone(&job_rx);
+            let work = Arc::clone(&work);
+            let guard = guard.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                loop {
+                    let job = job_rx.lock().await.recv().await;
+                    let Some(job) = job else {
+                        break;
+                    };
+                    // 取到任务后再等待许可, 空闲的工作任务不占用许可, 内存紧张时监视任务能及时收回;
+                    // 内存紧张时大文件也使用普通缓冲区
+                    let (_permit, buffers) = match &guard {
+                        Some(guard) => {
+                            let permit = guard.acquire().await;
+                            if guard.is_reduced() {
+                                (permit, buffers.reduced())
+                            } else {
+                                (permit, buffers)
+                            }
+                        }
+                        None => (None, buffers),
+                    };
+                    // 接收端已提前返回时停止工作
+                    if tx.send(work(job, buffers).await).await.is_err() {
+                        break;
+                    }
+                }
+            })
+        })
+        .collect();
+    (job_tx, handles)
+}
+
+// 在单独的任务中提交任务, 队列已满时等待而不阻塞结果的接收
+pub(crate) fn spawn_feeder<J: Send + 'static>(
+    job_tx: mpsc::Sender<J>,
+    jobs: Vec<J>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        for job in jobs {
+            // 工作任务已停止时不再提交
+            if job_tx.send(job).await.is_err() {
+                break;
+            }
+        }
+    })
+}
+
+fn receive_hash(
+    result: HashTaskResult,
+    hash_cache: &mut HashMap<PathBuf, HashRecord>,
+    journal: Option<&Journal>,
+    on_event: &mut impl FnMut(Event<'_>),
+) -> Result<()> {
+    let (file_path, (record, bytes, content_type)) = result?;
+    if let Some(journal) = journal {
+        journal.append(&file_path, &record)?;
+    }
+    on_event(Event::Hashed {
+        file_path: &file_path,
+        hash: &record.hash,
+        bytes,
+        content_type,
+    });
+    hash_cache.insert(file_path, record);
+    Ok(())
+}
+
+async fn hash_file(
+    source: &dyn Source,
+    file_path: &Path,
+    meta: FileMeta,
+    algorithm: &str,
+    companion_formats: &[CompanionFormat],
+    buffers: BufferSizes,
+) -> Result<(HashRecord, u64, ContentType)> {
+    let output = hash_source_file(source, file_path, algorithm, companion_formats, buffers).await?;
+    let record = HashRecord {
+        hash: output.hash,
+        meta: Some(meta),
+        companions: output.companions,
+    };
+    Ok((record, output.bytes, output.content_type))
+}
+
+pub(crate) fn abort_all_async_tasks(handles: &[JoinHandle<()>]) {
+    for handle in handles {
+        handle.abort();
+    }
+}
+
+pub(crate) async fn await_all_async_tasks(handles: Vec<JoinHandle<()>>) -> Result<()> {
+    for handle in handles {
+        handle.await.map_err(Error::task)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestTree;
+    use std::fs::File;
+    use std::time::{Duration, SystemTime};
+
+    fn touch(file_path: &Path, mtime: SystemTime) {
+        File::options()
+            .write(true)
+            .open(file_path)
+            .and_then(|file| file.set_modified(mtime))
+            .unwrap();
+    }
+
+    // 复用哈希时保留清单中原有的元数据, 多次更新的偏差不会累积
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reused_hashes_keep_the_stored_meta() {
+        let tree = TestTree::new();
+        let file_path = tree.file("a.txt", "a");
+        let base = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        touch(&file_path, base);
+        let manifest = tree.generate().await;
+        let stored = manifest.get(&file_path).unwrap().meta.unwrap();
+
+        let generator = HashGenerator::new().mtime_tolerance(2);
+        let mut updated = manifest;
+        for step in 1..=2 {
+            touch(&file_path, base + Duration::from_secs(step));
+            updated = generator.update(&updated, |_| {}).await.unwrap();
+            let meta = updated.get(&file_path).unwrap().meta.unwrap();
+            assert_eq!(meta.mtime, stored.mtime);
+        }
+        // 与原来的修改时间相差3秒, 超出容差后重新计算哈希
+        touch(&file_path, base + Duration::from_secs(3));
+        updated = generator.update(&updated, |_| {}).await.unwrap();
+        let meta = updated.get(&file_path).unwrap().meta.unwrap();
+        assert_eq!(meta.mtime, stored.mtime + 3);
+
+        let generator = HashGenerator::new().size_only(true);
+        touch(&file_path, base + Duration::from_secs(3600));
+        let updated = generator.update(&updated, |_| {}).await.unwrap();
+        assert_eq!(
+            updated.get(&file_path).unwrap().meta.unwrap().mtime,
+            meta.mtime
+        );
+    }
+
+    // 中断的刷新留下的日志中没有损坏文件的新哈希
+    #[tokio::test(flavor = "multi_thread")]
+    async fn refresh_does_not_journal_corrupted_files() {
+        let tree = TestTree::golden();
+        let manifest = tree.generate().await;
+        let hello_path = tree.join("hello.txt");
+        let stored = manifest.get(&hello_path).unwrap().meta.unwrap();
+        tree.corrupt("hello.txt");
+        let mtime = SystemTime::UNIX_EPOCH + Duration::new(stored.mtime as u64, stored.mtime_nanos);
+        touch(&hello_path, mtime);
+
+        let output = TestTree::new();
+        let hash_file_path = output.join("hash.xxh");
+        let (journal, _) =
+            Journal::open(&hash_file_path, tree.path(), manifest.algorithm()).unwrap();
+        let journal = Arc::new(journal);
+        let (_, report) = HashGenerator::new()
+            .journal(Arc::clone(&journal))
+            .refresh(&manifest, |_| {})
+            .await
+            .unwrap();
+        tree.assert_status(&report, "hello.txt", VerifyStatus::Failed);
+        drop(journal);
+
+        let (_, replayed) =
+            Journal::open(&hash_file_path, tree.path(), manifest.algorithm()).unwrap();
+        assert!(replayed.get(&hello_path).is_none());
+        assert!(replayed.get(&tree.join("zeros.bin")).is_some());
+    }
+}
@@ -0,0 +1,167 @@
+use crate::format::{
+    format_line, parse_line, read_lines, ManifestFormat, ManifestLine, MANIFEST_VERSION,
+};
+use crate::{create_hasher, CompanionHashes, Digest, Error, HashRecord, Manifest, Result};
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// 每追加这么多条记录同步一次到磁盘, 断电时最多需要重新计算这些文件的哈希
+const SYNC_INTERVAL: usize = 256;
+
+/// 清单的预写日志: 计算出的每个哈希先追加到清单旁的日志文件, 清单保存成功后删除日志
+/// 运行中断时清单保持原样, 日志保留下来, 下次运行时重放其中的条目, 不必重新计算这些文件
+pub struct Journal {
+    journal_path: PathBuf,
+    folder_path: PathBuf,
+    algorithm: String,
+    writer: Mutex<JournalWriter>,
+}
+
+struct JournalWriter {
+    file: File,
+    unsynced: usize,
+}
+
+impl Journal {
+    /// 打开清单对应的日志, 返回之前中断的运行留下的条目
+    /// 算法不同的旧日志无法复用, 直接清空
+    pub fn open(
+        hash_file_path: &Path,
+        folder_path: &Path,
+        algorithm: &str,
+    ) -> Result<(Journal, Manifest)> {
+        let journal_path = journal_path(hash_file_path);
+        let replayed = match File::open(&journal_path) {
+            Ok(file) => replay(folder_path, BufReader::new(file))
+                .filter(|replayed| replayed.algorithm() == algorithm)
+                .unwrap_or_else(|| Manifest::new(folder_path, algorithm)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                Manifest::new(folder_path, algorithm)
+            }
+            Err(err) => return Err(Error::io(&journal_path, err)),
+        };
+
+        let mut options = OpenOptions::new();
+        if replayed.is_empty() {
+            options.write(true).truncate(true);
+        } else {
+            options.append(true);
+        }
+        let mut file = options
+            .create(true)
+            .open(&journal_path)
+            .map_err(|err| Error::io(&journal_path, err))?;
+        // 新日志先写清单头; 续写旧日志时先换行, 避免与中断时写了一半的行连在一起
+        let header = if replayed.is_empty() {
+            format!(
+                "# version: {}\n# algorithm: {}\n",
+                MANIFEST_VERSION, algorithm
+            )
+        } else {
+            "\n".to_string()
+        };
+        file.write_all(header.as_bytes())
+            .map_err(|err| Error::io(&journal_path, err))?;
+
+        let journal = Journal {
+            journal_path,
+            folder_path: folder_path.to_path_buf(),
+            algorithm: algorithm.to_string(),
+            writer: Mutex::new(JournalWriter { file, unsynced: 0 }),
+        };
+        Ok((journal, replayed))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.journal_path
+    }
+
+    pub fn append(&self, file_path: &Path, record: &HashRecord) -> Result<()> {
+        let entry_path = file_path
+            .strip_prefix(&self.folder_path)
+            .unwrap_or(file_path);
+        let line = format_line(
+            ManifestFormat::Native,
+            &self.algorithm,
+            entry_path,
+            &record.hash.to_string(),
+            record.meta,
+        )?;
+        let mut writer = self.writer.lock().unwrap_or_else(|err| err.into_inner());
+        // 一条记录一次写入, 中断时最多留下不完整的最后一行, 重放时会被忽略
+        writer
+            .file
+            .write_all(format!("{}\n", line).as_bytes())
+            .map_err(|err| Error::io(&self.journal_path, err))?;
+        writer.unsynced += 1;
+        if writer.unsynced >= SYNC_INTERVAL {
+            writer
+                .file
+                .sync_data()
+                .map_err(|err| Error::io(&self.journal_path, err))?;
+            writer.unsynced = 0;
+        }
+        Ok(())
+    }
+
+    /// 清单已经保存, 日志中的条目不再需要
+    pub fn commit(self) -> Result<()> {
+        drop(self.writer);
+        match fs::remove_file(&self.journal_path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                Err(Error::io(&self.journal_path, err))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// 日志保存在清单旁的隐藏文件中, 与锁文件的命名方式相同
+pub fn journal_path(hash_file_path: &Path) -> PathBuf {
+    let mut file_name = OsString::from(".");
+    file_name.push(hash_file_path.file_name().unwrap_or_default());
+    file_name.push(".journal");
+    hash_file_path.with_file_name(file_name)
+}
+
+// 逐行解析日志, 跳过中断时写了一半的行和无法解码的行; 没有可用的算法头时返回 None
+fn replay(folder_path: &Path, reader: impl BufRead) -> Option<Manifest> {
+    let mut manifest = None;
+    let mut digest_len = 0;
+    for line in read_lines(reader) {
+        let line = match line {
+            Ok(line) => line,
+            Err(Error::InvalidEncoding { .. }) => continue,
+            Err(_) => break,
+        };
+        match parse_line(&line, MANIFEST_VERSION) {
+            Ok(Some(ManifestLine::Header {
+                key: "algorithm",
+                value,
+            })) => {
+                digest_len = create_hasher(value).ok()?.digest_len();
+                manifest = Some(Manifest::new(folder_path, value));
+            }
+            Ok(Some(ManifestLine::Entry {
+                path, hash, meta, ..
+            })) => {
+                let Ok(hash) = Digest::from_hex(hash, digest_len) else {
+                    continue;
+                };
+                if let Some(manifest) = &mut manifest {
+                    let record = HashRecord {
+                        hash,
+                        meta,
+                        companions: CompanionHashes::default(),
+                    };
+                    manifest.insert(folder_path.join(path), record);
+                }
+            }
+            _ => {}
+        }
+    }
+    manifest
+}
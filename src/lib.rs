@@ -1,102 +1,209 @@
-use std::collections::HashMap;
-use std::fs::{self, File, OpenOptions};
-use std::io::{self, BufRead, BufReader, Write};
+//! 按清单计算和校验目录中文件的哈希
+//!
+//! 库的公开接口只包括从根模块重新导出的条目, 各子模块本身不公开:
+//! [`walk`] 和 [`WalkOptions`] 遍历目录, [`HashGenerator`] 计算哈希和生成清单,
+//! [`Manifest`] 读写和比较清单, [`Verifier`] 按清单校验文件,
+//! [`Event`] 和 [`VerifyReport`] 报告运行过程中的事件和校验结果
+//!
+//! 结果和配置类型标记为 `#[non_exhaustive]`, 以后增加变体或字段不算破坏兼容;
+//! 库外需要构造的类型通过 new 和构建方法创建
+mod algorithm;
+mod anomaly;
+mod content;
+mod format;
+mod hash;
+mod journal;
+mod lint;
+mod manifest;
+mod memory;
+mod progress;
+mod report;
+mod sort;
+mod source;
+mod store;
+mod template;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+mod verify;
+mod walk;
+
+pub use algorithm::{
+    algorithm_names, create_hasher, register_algorithm, Digest, HasherFactory, StreamingHasher,
+    DEFAULT_ALGORITHM,
+};
+pub use anomaly::{Anomaly, AnomalyDetector};
+pub use content::ContentType;
+pub use format::{ManifestFormat, OutsideRoot};
+pub use hash::{
+    compute_hash, compute_hash_with_companions, get_file_meta, CompanionPolicy, HashGenerator,
+};
+pub use journal::{journal_path, Journal};
+pub use lint::{lint_manifest, LintIssue, LintProblem, LintReport};
+pub use manifest::{
+    stale_artifacts, CompanionFormat, CompanionHashes, DuplicateSet, FileMeta, HashRecord,
+    Manifest, ManifestDiff, SubsetFilter,
+};
+pub use progress::Progress;
+pub use report::{Event, VerifyReport, VerifyStatus};
+pub use sort::SortOrder;
+pub use source::{LocalSource, OpenFuture, Source, SourceReader};
+pub use store::{sidecar_paths, HttpStore, ManifestLock, ManifestStore, TextFileStore};
+pub use template::{expand_template, prune_outputs};
+pub use verify::Verifier;
+pub use walk::{
+    get_all_file_path, walk, walk_files, walk_with_options, GlobPattern, SkipReason, Walk,
+    WalkEvent, WalkOptions,
+};
+
+use std::fmt;
+use std::io;
 use std::path::{Path, PathBuf};
-use tokio::io::AsyncReadExt;
-use xxhash_rust::xxh3::Xxh3;
 
-pub fn get_all_file_path(dir: &Path) -> Vec<PathBuf> {
-    let mut file_paths = Vec::new();
+/// 库中所有操作返回的错误, 通过 Display 输出面向用户的说明
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    InvalidHash {
+        value: String,
+    },
+    InvalidMeta {
+        path: String,
+    },
+    InvalidPath {
+        value: String,
+    },
+    InvalidEncoding {
+        line: usize,
+    },
+    UnrecognizedLine {
+        line: usize,
+    },
+    Read(io::Error),
+    UnsupportedVersion(String),
+    ManifestConflict(PathBuf),
+    InvalidTemplate(String),
+    Http {
+        url: String,
+        message: String,
+    },
+    ReadOnlyStore,
+    AlgorithmMismatch {
+        expected: String,
+        found: String,
+    },
+    InvalidFraction(f64),
+    InvalidSlice {
+        index: usize,
+        count: usize,
+    },
+    MissingHash(PathBuf),
+    UnsupportedCompanionFormat(String),
+    UnsupportedAlgorithm(String),
+    UnsupportedFormat(String),
+    UnsupportedSortOrder(String),
+    UnsupportedOutsideRoot(String),
+    OutsideRoot(PathBuf),
+    InvalidPattern {
+        pattern: String,
+        message: String,
+    },
+    SymlinkLoop(PathBuf),
+    IncompatibleFormat {
+        format: ManifestFormat,
+        algorithm: String,
+    },
+    Task(String),
+}
 
-    if let Ok(entries) = fs::read_dir(dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.is_file() {
-                file_paths.push(path);
-            } else if path.is_dir() {
-                file_paths.extend(get_all_file_path(&path));
-            }
+impl Error {
+    pub(crate) fn io(path: &Path, source: io::Error) -> Error {
+        Error::Io {
+            path: path.to_path_buf(),
+            source,
         }
     }
-    file_paths
-}
 
-pub async fn compute_hash(file_path: &PathBuf) -> tokio::io::Result<u128> {
-    let file = tokio::fs::File::open(file_path).await?;
-    let mut reader = tokio::io::BufReader::new(file);
-    let mut xxh3 = Xxh3::new();
-    let mut buf = vec![0; 32768];
-    loop {
-        let n = reader.read(&mut buf).await?;
-        if n == 0 {
-            break;
-        }
-        xxh3.update(&buf[..n]);
+    // 异步任务的错误只保留说明文字, 公开接口中不出现 tokio 的类型
+    pub(crate) fn task(err: tokio::task::JoinError) -> Error {
+        Error::Task(err.to_string())
     }
-    let xxh3_hash = xxh3.digest128();
-    Ok(xxh3_hash)
-}
 
-pub fn export_all_hash(
-    hash_file_path: &Path,
-    hash_cache: &HashMap<PathBuf, u128>,
-    file_paths: &[PathBuf],
-    folder_path: &Path,
-) -> std::io::Result<()> {
-    let mut file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open(hash_file_path)?;
+    /// 访问的文件不存在
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Error::Io { source, .. } if source.kind() == io::ErrorKind::NotFound)
+    }
+}
 
-    for file_path in file_paths {
-        let hash = match hash_cache.get(file_path) {
-            Some(hash) => hash,
-            None => {
-                return Err(tokio::io::Error::new(
-                    tokio::io::ErrorKind::Other,
-                    format!("排序哈希时找不到[{}]的哈希", file_path.display()),
-                ));
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => {
+                write!(f, "访问[{}]时出现错误: {}", path.display(), source)
+            }
+            Error::InvalidHash { value } => write!(f, "无法把[{}]解析为哈希值", value),
+            Error::InvalidMeta { path } => write!(f, "无法解析[{}]的大小和修改时间", path),
+            Error::InvalidPath { value } => write!(f, "无法解析转义后的路径[{}]", value),
+            Error::InvalidEncoding { line } => write!(f, "清单第{}行不是有效的UTF-8文本", line),
+            Error::UnrecognizedLine { line } => write!(f, "无法识别清单第{}行", line),
+            Error::Read(err) => write!(f, "读取清单时出现错误: {}", err),
+            Error::UnsupportedVersion(version) => write!(f, "不支持的清单版本: {}", version),
+            Error::ManifestConflict(path) => {
+                write!(f, "清单[{}]在读取后被其他进程修改", path.display())
+            }
+            Error::Http { url, message } => write!(f, "下载[{}]时出现错误: {}", url, message),
+            Error::ReadOnlyStore => write!(f, "该清单来源不支持写入"),
+            Error::InvalidTemplate(template) => write!(f, "无效的路径模板: {}", template),
+            Error::AlgorithmMismatch { expected, found } => {
+                write!(f, "清单使用的哈希算法不同: {} 和 {}", expected, found)
             }
-        };
-        writeln!(
-            file,
-            "[{} | {:x}]",
-            file_path.strip_prefix(folder_path).unwrap().display(),
-            hash
-        )?;
+            Error::InvalidFraction(fraction) => {
+                write!(f, "抽样比例必须在0到1之间: {}", fraction)
+            }
+            Error::InvalidSlice { index, count } => {
+                write!(f, "无效的分片: 第{}片, 共{}片", index, count)
+            }
+            Error::MissingHash(path) => write!(f, "找不到[{}]的哈希", path.display()),
+            Error::UnsupportedCompanionFormat(name) => write!(f, "不支持的附加格式: {}", name),
+            Error::UnsupportedAlgorithm(name) => write!(f, "不支持的哈希算法: {}", name),
+            Error::UnsupportedFormat(name) => write!(f, "不支持的清单格式: {}", name),
+            Error::UnsupportedSortOrder(name) => write!(f, "不支持的排序方式: {}", name),
+            Error::UnsupportedOutsideRoot(name) => {
+                write!(f, "不支持的文件夹外条目处理方式: {}", name)
+            }
+            Error::OutsideRoot(path) => {
+                write!(f, "[{}]不在清单的文件夹中", path.display())
+            }
+            Error::InvalidPattern { pattern, message } => {
+                write!(f, "无效的匹配模式[{}]: {}", pattern, message)
+            }
+            Error::SymlinkLoop(path) => write!(f, "符号链接[{}]形成循环", path.display()),
+            Error::IncompatibleFormat { format, algorithm } => write!(
+                f,
+                "清单格式[{}]不支持哈希算法[{}]",
+                format.name(),
+                algorithm
+            ),
+            Error::Task(err) => write!(f, "等待异步任务完成时出现错误: {}", err),
+        }
     }
-    Ok(())
 }
 
-pub fn read_hash_file(
-    folder_path: &Path,
-    hash_file_path: &Path,
-) -> io::Result<HashMap<PathBuf, u128>> {
-    let mut hash_map = HashMap::new();
-
-    let file = File::open(hash_file_path)?;
-    let reader = BufReader::new(file);
-
-    for line in reader.lines().flatten() {
-        let parts: Vec<&str> = line
-            .trim_matches(|c| c == '[' || c == ']' || c == ' ')
-            .split(" | ")
-            .collect();
-        if parts.len() == 2 {
-            let mut key = folder_path.to_path_buf();
-            key.push(parts[0]);
-            let value = match u128::from_str_radix(parts[1], 16) {
-                Ok(value) => value,
-                Err(err) => {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("无法把[{}]转换为u128: {}", parts[1], err),
-                    ))
-                }
-            };
-            hash_map.insert(key, value);
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Read(err) => Some(err),
+            _ => None,
         }
     }
-    Ok(hash_map)
 }
+
+/// 错误类型为 [`Error`] 的结果
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// 读取文件时默认的缓冲区字节数
+pub const DEFAULT_BUFFER_SIZE: usize = 32768;
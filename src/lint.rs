@@ -0,0 +1,295 @@
+use crate::format::{
+    normalize_path, parse_line, read_lines, ManifestFormat, ManifestLine, MANIFEST_VERSION,
+};
+use crate::{
+    create_hasher, CompanionHashes, Digest, Error, FileMeta, HashRecord, Manifest, Result,
+    DEFAULT_ALGORITHM,
+};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+
+/// 清单检查发现的问题
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum LintProblem {
+    Unparsable(String),
+    InvalidEncoding,
+    UnrecognizedLine,
+    UnsupportedVersion(String),
+    MissingHeader(&'static str),
+    DuplicatePath(PathBuf),
+    InvalidHash(String),
+    MixedSeparators(PathBuf),
+    NotNormalized(PathBuf),
+}
+
+impl LintProblem {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LintProblem::Unparsable(_) => "unparsable",
+            LintProblem::InvalidEncoding => "invalid_encoding",
+            LintProblem::UnrecognizedLine => "unrecognized_line",
+            LintProblem::UnsupportedVersion(_) => "unsupported_version",
+            LintProblem::MissingHeader(_) => "missing_header",
+            LintProblem::DuplicatePath(_) => "duplicate_path",
+            LintProblem::InvalidHash(_) => "invalid_hash",
+            LintProblem::MixedSeparators(_) => "mixed_separators",
+            LintProblem::NotNormalized(_) => "not_normalized",
+        }
+    }
+
+    /// 修复时只能补全清单头, 去掉重复条目和统一路径, 无法解析的行不能自动修复
+    pub fn is_fixable(&self) -> bool {
+        matches!(
+            self,
+            LintProblem::MissingHeader(_)
+                | LintProblem::DuplicatePath(_)
+                | LintProblem::MixedSeparators(_)
+                | LintProblem::NotNormalized(_)
+        )
+    }
+}
+
+impl fmt::Display for LintProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LintProblem::Unparsable(message) => write!(f, "无法解析: {}", message),
+            LintProblem::InvalidEncoding => write!(f, "不是有效的UTF-8文本"),
+            LintProblem::UnrecognizedLine => write!(f, "无法识别的行"),
+            LintProblem::UnsupportedVersion(version) => write!(f, "不支持的清单版本: {}", version),
+            LintProblem::MissingHeader(key) => write!(f, "缺少清单头: {}", key),
+            LintProblem::DuplicatePath(path) => write!(f, "重复的路径: {}", path.display()),
+            LintProblem::InvalidHash(hash) => write!(f, "无效的哈希值: {}", hash),
+            LintProblem::MixedSeparators(path) => {
+                write!(f, "路径中使用了反斜杠分隔符: {}", path.display())
+            }
+            LintProblem::NotNormalized(path) => {
+                write!(f, "路径不是NFC形式: {}", path.display())
+            }
+        }
+    }
+}
+
+/// 清单中的一个问题及其所在的行
+#[non_exhaustive]
+pub struct LintIssue {
+    /// 缺少清单头等与具体行无关的问题没有行号
+    pub line: Option<usize>,
+    pub problem: LintProblem,
+}
+
+/// 清单检查的结果
+#[non_exhaustive]
+pub struct LintReport {
+    pub issues: Vec<LintIssue>,
+    /// 去掉重复的条目并统一路径后的清单, 所有问题都可以修复时写回即可修复
+    /// 无法修复的行不在其中, 写回会丢失这些条目
+    pub fixed: Manifest,
+}
+
+impl LintReport {
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    pub fn is_fixable(&self) -> bool {
+        self.issues.iter().all(|issue| issue.problem.is_fixable())
+    }
+}
+
+/// 逐行检查清单的结构, 不读取清单中列出的文件
+pub fn lint_manifest(folder_path: &Path, reader: impl BufRead) -> Result<LintReport> {
+    let mut issues = Vec::new();
+    let mut headers = HashSet::new();
+    let mut header_algorithm = None;
+    let mut entry_algorithm = None;
+    let mut format = ManifestFormat::Native;
+    let mut timestamps = true;
+    let mut run_id = None;
+    let mut version = 1;
+    let mut entries: Vec<(usize, PathBuf, String, Option<FileMeta>)> = Vec::new();
+
+    for (index, line) in read_lines(reader).enumerate() {
+        let line_number = index + 1;
+        let mut issue = |problem| {
+            issues.push(LintIssue {
+                line: Some(line_number),
+                problem,
+            })
+        };
+        // 无法解码的行报告后继续检查后面的行
+        let line = match line {
+            Ok(line) => line,
+            Err(Error::InvalidEncoding { .. }) => {
+                issue(LintProblem::InvalidEncoding);
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_line(&line, version) {
+            Ok(Some(ManifestLine::Header { key, value })) => {
+                headers.insert(key.to_string());
+                match key {
+                    "algorithm" => header_algorithm = Some(value.to_string()),
+                    "timestamps" => timestamps = value != "no",
+                    "run" => run_id = Some(value.to_string()),
+                    "version" => match value.parse() {
+                        Ok(value) if (1..=MANIFEST_VERSION).contains(&value) => version = value,
+                        _ => issue(LintProblem::UnsupportedVersion(value.to_string())),
+                    },
+                    _ => {}
+                }
+            }
+            Ok(Some(ManifestLine::Entry {
+                format: entry_format,
+                algorithm,
+                path,
+                hash,
+                meta,
+            })) => {
+                if entries.is_empty() {
+                    format = entry_format;
+                    entry_algorithm = algorithm;
+                }
+                // 旧版格式和未转义的GNU格式把 \ 当作路径分隔符
+                let legacy = match entry_format {
+                    ManifestFormat::Native => version < 2,
+                    _ => !line.starts_with('\\'),
+                };
+                if legacy && line.contains('\\') {
+                    issue(LintProblem::MixedSeparators(path.clone()));
+                }
+                entries.push((line_number, path, hash.to_string(), meta));
+            }
+            Ok(None) if line.starts_with('#') => {}
+            Ok(None) => issue(LintProblem::UnrecognizedLine),
+            Err(err) => issue(LintProblem::Unparsable(err.to_string())),
+        }
+    }
+
+    // 原生格式需要版本和算法头, xxhsum和BSD格式不写清单头
+    if format == ManifestFormat::Native {
+        for key in ["version", "algorithm"] {
+            if !headers.contains(key) {
+                issues.push(LintIssue {
+                    line: None,
+                    problem: LintProblem::MissingHeader(key),
+                });
+            }
+        }
+    }
+
+    let algorithm = header_algorithm
+        .as_deref()
+        .or(entry_algorithm)
+        .unwrap_or(DEFAULT_ALGORITHM);
+    let digest_len = create_hasher(algorithm)?.digest_len();
+    let mut fixed = Manifest::new(folder_path, algorithm);
+    fixed.set_format(format);
+    fixed.set_timestamps(timestamps);
+    if let Some(run_id) = run_id {
+        fixed.set_run_id(&run_id);
+    }
+
+    // 重复的路径只保留第一次出现的条目
+    let mut seen = HashSet::new();
+    for (line_number, path, hash, meta) in entries {
+        let mut issue = |problem| {
+            issues.push(LintIssue {
+                line: Some(line_number),
+                problem,
+            })
+        };
+        let normalized = normalize_path(&path);
+        if let Cow::Owned(_) = normalized {
+            issue(LintProblem::NotNormalized(path.clone()));
+        }
+        if !seen.insert(normalized.into_owned()) {
+            issue(LintProblem::DuplicatePath(path));
+            continue;
+        }
+        match Digest::from_hex(&hash, digest_len) {
+            Ok(hash) => fixed.insert(
+                folder_path.join(&path),
+                HashRecord {
+                    hash,
+                    meta,
+                    companions: CompanionHashes::default(),
+                },
+            ),
+            Err(_) => issue(LintProblem::InvalidHash(hash)),
+        }
+    }
+
+    issues.sort_by_key(|issue| issue.line);
+    Ok(LintReport { issues, fixed })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lint(contents: &[u8]) -> LintReport {
+        lint_manifest(Path::new("root"), contents).unwrap()
+    }
+
+    fn problems(report: &LintReport) -> Vec<(Option<usize>, &'static str)> {
+        report
+            .issues
+            .iter()
+            .map(|issue| (issue.line, issue.problem.name()))
+            .collect()
+    }
+
+    #[test]
+    fn clean_manifest_has_no_issues() {
+        let report = lint(crate::testing::GOLDEN_MANIFEST.as_bytes());
+        assert!(report.is_clean());
+        assert_eq!(report.fixed.len(), 5);
+    }
+
+    #[test]
+    fn lines_after_invalid_utf8_are_still_checked() {
+        let report = lint(
+            b"# version: 3\n# algorithm: xxh3-128\n[\xff | 00]\n\
+              [a | 99aa06d3014798d86001c324468d497f]\n\
+              [a | 99aa06d3014798d86001c324468d497f]\n",
+        );
+        assert_eq!(
+            problems(&report),
+            [(Some(3), "invalid_encoding"), (Some(5), "duplicate_path")]
+        );
+        assert!(!report.is_fixable());
+    }
+
+    #[test]
+    fn invalid_hashes_are_not_fixable() {
+        let report = lint(b"# version: 3\n# algorithm: xxh3-128\n[a | xyz]\n");
+        assert_eq!(problems(&report), [(Some(3), "invalid_hash")]);
+        assert!(!report.is_fixable());
+    }
+
+    #[test]
+    fn missing_headers_and_duplicates_are_fixable() {
+        let report = lint(
+            b"[a | 99aa06d3014798d86001c324468d497f]\n\
+              [a | 99aa06d3014798d86001c324468d497f]\n",
+        );
+        assert_eq!(
+            problems(&report),
+            [
+                (None, "missing_header"),
+                (None, "missing_header"),
+                (Some(2), "duplicate_path")
+            ]
+        );
+        assert!(report.is_fixable());
+        assert_eq!(report.fixed.len(), 1);
+    }
+}
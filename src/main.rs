@@ -1,14 +1,39 @@
-use crossbeam_channel::{bounded, Receiver};
 use mimalloc::MiMalloc;
-use std::collections::HashMap;
+use notify::{EventKind, RecursiveMode, Watcher};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
 use std::env;
-use std::io::{self, ErrorKind};
+use std::fs;
+use std::io::{self, IsTerminal, Write};
 use std::path::{Path, PathBuf};
 use std::process::exit;
-use std::sync::Arc;
-use tokio::sync::{Semaphore, SemaphorePermit};
-use tokio::task::JoinHandle;
-use xxhash_verify::{compute_hash, export_all_hash, get_all_file_path, read_hash_file};
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::sync::{Arc, LazyLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncBufReadExt, BufReader, Lines, Stdin};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+use xxhash_verify::{
+    expand_template, lint_manifest, prune_outputs, sidecar_paths, stale_artifacts, Anomaly,
+    AnomalyDetector, CompanionFormat, CompanionPolicy, DuplicateSet, Error, Event, HashGenerator,
+    HttpStore, Journal, LintIssue, Manifest, ManifestFormat, ManifestLock, ManifestStore,
+    OutsideRoot, Progress, SkipReason, SortOrder, TextFileStore, Verifier, VerifyReport,
+    VerifyStatus, WalkOptions, DEFAULT_ALGORITHM, DEFAULT_BUFFER_SIZE,
+};
+
+// 每次运行生成唯一的ID, 写入JSON输出, 清单和每一行错误与警告, 以便关联同一次运行的输出
+static RUN_ID: LazyLock<String> = LazyLock::new(|| Uuid::new_v4().to_string());
+
+// 输出到标准错误的行都带上运行ID
+macro_rules! log_stderr {
+    ($($arg:tt)*) => {
+        eprintln!("[运行 | {}] {}", *RUN_ID, format_args!($($arg)*))
+    };
+}
+
+// JSON输出队列默认最多缓存的行数
+const DEFAULT_OUTPUT_QUEUE: usize = 1024;
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
@@ -19,50 +44,394 @@ async fn main() {
     let args = match Args::parse_args(&args) {
         Ok(args) => args,
         Err(err) => {
-            eprintln!("解析参数时出现错误: {}", err);
+            log_stderr!("解析参数时出现错误: {}", err);
             exit(1)
         }
     };
 
-    // 创建任务信号量
-    let task_semaphore = Arc::new(Semaphore::new(16));
+    // 清单保存在文本文件中
+    let store = TextFileStore::new(&args.hash_file_path);
+
+    // 创建输出报告器
+    let mut reporter = Reporter::new(&args);
+    reporter.started();
 
     match args.model {
+        Model::Check if args.refresh => {
+            // 改写清单期间持有锁
+            let _lock = lock_manifest(&store);
+            let old_manifest = read_manifest(&store, &args);
+            // 刷新会重新计算所有文件, 不能复用日志中的哈希, 也不能删掉中断的生成或更新留下的日志
+            let (journal, replayed) = open_journal(&args, old_manifest.algorithm());
+            if !replayed.is_empty() {
+                log_stderr!(
+                    "预写日志[{}]中有上次中断的运行留下的 {} 个哈希, 请先用更新模式 (-u) 完成恢复再刷新",
+                    journal.path().display(),
+                    replayed.len()
+                );
+                exit(1);
+            }
+
+            // 读取一次文件, 同时校验旧清单和生成新清单
+            let result = hash_generator(&args)
+                .algorithm(old_manifest.algorithm())
+                .journal(Arc::clone(&journal))
+                .refresh(&old_manifest, |event| reporter.handle(event))
+                .await;
+            let (mut manifest, report) = match result {
+                Ok(result) => result,
+                Err(err) => {
+                    reporter.finish();
+                    log_stderr!("校验哈希时出现错误: {}", err);
+                    exit(1)
+                }
+            };
+            reporter.finish();
+
+            // 损坏的文件在新清单中保留旧的哈希
+            apply_output_options(&mut manifest, &args);
+            write_manifest(&store, &manifest);
+            commit_journal(journal);
+            if !report.is_ok() {
+                exit(1);
+            }
+        }
         Model::Check => {
+            // 读取哈希文件, 也可以是 rclone hashsum 的输出
+            let mut manifest = match args.rclone {
+                Some(hash_type) => read_rclone_manifest(&args, hash_type),
+                None => read_manifest(&store, &args),
+            };
+
+            // 只校验前一部分文件, 报告中标明是部分校验
+            if args.max_files.is_some() || args.max_bytes.is_some() {
+                let limited = manifest.limit(args.max_files, args.max_bytes);
+                reporter.set_partial(limited.len(), manifest.len());
+                manifest = limited;
+            }
+
             // 开始校验哈希
-            let handles = model_check(args, task_semaphore);
+            let result = if args.stdin_paths {
+                verify_stdin_paths(&manifest, &args, &mut reporter).await
+            } else {
+                args.verifier
+                    .run(&manifest, |event| reporter.handle(event))
+                    .await
+            }
+            .map(|report| report.is_ok());
+            let ok = match result {
+                Ok(ok) => ok,
+                Err(err) => {
+                    reporter.finish();
+                    log_stderr!("校验哈希时出现错误: {}", err);
+                    exit(1)
+                }
+            };
+            reporter.finish();
 
-            // 等待所有异步任务完成
-            await_all_async_tasks(handles).await;
+            // 有文件校验失败, 缺失或不在清单中时返回1, 易变文件不一致不影响返回值
+            if !ok {
+                exit(1);
+            }
+        }
+        Model::Remote => {
+            // 第三个参数是子目录和上游清单地址的对应表
+            let mappings = match read_mappings(&args.hash_file_path) {
+                Ok(mappings) => mappings,
+                Err(err) => {
+                    log_stderr!("读取清单地址列表时出现错误: {}", err);
+                    exit(1)
+                }
+            };
+
+            // 先下载所有上游清单, 互相重叠的条目只校验一次
+            let mut manifests = Vec::new();
+            for (sub_folder, url) in &mappings {
+                let folder_path = args.folder_path.join(sub_folder);
+                match tokio::task::block_in_place(|| HttpStore::new(url).load(&folder_path)) {
+                    Ok(manifest) => {
+                        check_entry_paths(&manifest, &args);
+                        manifests.push(manifest)
+                    }
+                    Err(err) => {
+                        log_stderr!("读取哈希值时出现错误: {}", err);
+                        exit(1)
+                    }
+                }
+            }
+            let reports = match args
+                .verifier
+                .run_many(&manifests, |event| reporter.handle(event))
+                .await
+            {
+                Ok(reports) => reports,
+                Err(err) => {
+                    reporter.finish();
+                    log_stderr!("校验哈希时出现错误: {}", err);
+                    exit(1)
+                }
+            };
+
+            // 分别报告每个上游清单的结果, 以便找出是哪个清单校验失败
+            for ((sub_folder, url), report) in mappings.iter().zip(&reports) {
+                reporter.manifest_summary(sub_folder, url, report);
+            }
+            reporter.finish();
+
+            // 与校验模式相同, 易变文件不一致不影响返回值
+            if !reports.iter().all(|report| report.is_ok()) {
+                exit(1);
+            }
         }
         Model::Generate => {
-            // 获取所有文件路径
-            let file_paths = Arc::new(get_all_file_path(args.folder_path));
+            // 改写清单期间持有锁
+            let _lock = lock_manifest(&store);
+
+            // 计算出的哈希先写入预写日志, 中断后再次运行时复用
+            let algorithm = args.algorithm.unwrap_or(DEFAULT_ALGORITHM);
+            let (journal, replayed) = open_journal(&args, algorithm);
+            reporter.resumed(journal.path(), replayed.len());
+
+            // 开始计算哈希
+            let mut generator = hash_generator(&args)
+                .algorithm(algorithm)
+                .also_emit(&args.also_emit)
+                .journal(Arc::clone(&journal));
+            if let Some(policy) = &args.companion_policy {
+                generator = generator.companion_policy(policy.clone());
+            }
+            let result = if args.stdin_paths {
+                hash_stdin_paths(&generator, &args, &mut reporter).await
+            } else if replayed.is_empty() || !args.also_emit.is_empty() || args.content_types {
+                // 日志中没有附加格式的哈希和内容类型, 需要时重新计算所有文件
+                generator
+                    .run(args.folder_path, |event| reporter.handle(event))
+                    .await
+            } else {
+                // 日志中的条目相当于上次生成的清单, 只计算剩下的文件
+                generator
+                    .update(&replayed, |event| {
+                        if !matches!(
+                            event,
+                            Event::Added(_) | Event::Changed(_) | Event::Removed(_)
+                        ) {
+                            reporter.handle(event)
+                        }
+                    })
+                    .await
+            };
+            let mut manifest = match result {
+                Ok(manifest) => manifest,
+                Err(err) => {
+                    reporter.finish();
+                    log_stderr!("计算哈希时出现错误: {}", err);
+                    exit(1)
+                }
+            };
 
-            // 开始计算哈希并发送到通道
-            let (rx, handles) = model_generate(&file_paths, task_semaphore);
+            reporter.finish();
 
-            // 创建哈希缓存
-            let mut hash_cache = HashMap::new();
+            // 把哈希写入文件
+            apply_output_options(&mut manifest, &args);
+            write_manifest(&store, &manifest);
+            drop(generator);
+            commit_journal(journal);
+
+            // 报告哈希相同的重复文件
+            if args.find_dupes {
+                reporter.duplicates(&manifest.duplicates());
+            }
+
+            // 把附加格式的哈希写入对应的文件
+            for companion_format in &args.also_emit {
+                if let Err(err) = manifest.write_companion(
+                    &args
+                        .hash_file_path
+                        .with_extension(companion_format.extension()),
+                    *companion_format,
+                ) {
+                    log_stderr!("写入附加格式的哈希到文件时出现错误: {}", err);
+                    exit(1);
+                };
+            }
 
-            // 从通道接收哈希并把哈希写入哈希缓存
-            for (file_path, hash) in rx.iter().take(handles.len()) {
-                hash_cache.insert(file_path, hash);
+            // 按保留策略清理之前用同一模板生成的文件
+            let mut templates = vec![args.hash_file_template.to_string()];
+            for companion_format in &args.also_emit {
+                let template =
+                    Path::new(args.hash_file_template).with_extension(companion_format.extension());
+                templates.push(template.to_string_lossy().into_owned());
             }
+            for template in &templates {
+                match prune_outputs(
+                    template,
+                    args.folder_path,
+                    args.keep_last,
+                    args.keep_days,
+                    SystemTime::now(),
+                ) {
+                    Ok(removed) => {
+                        for file_path in &removed {
+                            reporter.pruned(file_path);
+                        }
+                    }
+                    Err(err) => {
+                        log_stderr!("清理旧的哈希文件时出现错误: {}", err);
+                        exit(1);
+                    }
+                }
+            }
+        }
+        Model::Update => {
+            // 改写清单期间持有锁
+            let _lock = lock_manifest(&store);
 
-            // 等待所有异步任务完成
-            await_all_async_tasks(handles).await;
+            // 读取旧的哈希文件
+            let mut old_manifest = read_manifest(&store, &args);
 
-            // 把哈希缓存写入文件
-            if let Err(err) = export_all_hash(
-                args.hash_file_path,
-                &hash_cache,
-                &file_paths,
-                args.folder_path,
-            ) {
-                eprintln!("写入哈希到文件时出现错误: {}", err);
+            // 上次中断的更新已经计算出的哈希比旧清单中的更新
+            let (journal, replayed) = open_journal(&args, old_manifest.algorithm());
+            reporter.resumed(journal.path(), replayed.len());
+            for (file_path, record) in replayed.iter() {
+                old_manifest.insert(file_path.clone(), record.clone());
+            }
+
+            // 只重新计算改变文件的哈希
+            let mut manifest = match hash_generator(&args)
+                .journal(Arc::clone(&journal))
+                .update(&old_manifest, |event| reporter.handle(event))
+                .await
+            {
+                Ok(manifest) => manifest,
+                Err(err) => {
+                    reporter.finish();
+                    log_stderr!("更新哈希时出现错误: {}", err);
+                    exit(1)
+                }
+            };
+
+            reporter.finish();
+
+            // 把哈希写入文件
+            apply_output_options(&mut manifest, &args);
+            write_manifest(&store, &manifest);
+            commit_journal(journal);
+        }
+        Model::Watch => {
+            // 监视期间一直持有锁
+            let _lock = lock_manifest(&store);
+
+            watch(&args, &store, &mut reporter).await;
+            reporter.finish();
+        }
+        Model::Diff => {
+            // 读取旧的哈希文件
+            let old_manifest = read_manifest(&store, &args);
+
+            // 与另一个哈希文件比较, 或者只重新计算目录中改变文件的哈希
+            let new_manifest = match args.against {
+                Some(against) => read_manifest(&TextFileStore::new(against), &args),
+                None => match hash_generator(&args)
+                    .update(&old_manifest, |event| {
+                        if let Event::Warning(_) = event {
+                            reporter.handle(event)
+                        }
+                    })
+                    .await
+                {
+                    Ok(manifest) => manifest,
+                    Err(err) => {
+                        reporter.finish();
+                        log_stderr!("计算哈希时出现错误: {}", err);
+                        exit(1)
+                    }
+                },
+            };
+
+            let mut diff = match old_manifest.diff(&new_manifest) {
+                Ok(diff) => diff,
+                Err(err) => {
+                    log_stderr!("比较哈希时出现错误: {}", err);
+                    exit(1)
+                }
+            };
+            if let Some(sort_order) = args.sort_order {
+                diff.sort(sort_order);
+            }
+            for file_path in &diff.added {
+                reporter.handle(Event::Added(file_path));
+            }
+            for file_path in &diff.removed {
+                reporter.handle(Event::Removed(file_path));
+            }
+            for file_path in &diff.modified {
+                reporter.handle(Event::Changed(file_path));
+            }
+            reporter.finish();
+
+            // 与diff命令一致, 存在差异时返回1
+            if !diff.is_empty() {
                 exit(1);
+            }
+        }
+        Model::Lint => {
+            // 修复时从读取到改写清单一直持有锁, 避免覆盖其他进程在这期间写入的内容
+            let _lock = args.fix.then(|| lock_manifest(&store));
+
+            // 只检查清单本身, 不读取清单中列出的文件
+            let file = match fs::File::open(&args.hash_file_path) {
+                Ok(file) => file,
+                Err(err) => {
+                    log_stderr!("读取哈希文件时出现错误: {}", err);
+                    exit(1)
+                }
+            };
+            let report = match lint_manifest(args.folder_path, io::BufReader::new(file)) {
+                Ok(report) => report,
+                Err(err) => {
+                    log_stderr!("检查哈希文件时出现错误: {}", err);
+                    exit(1)
+                }
             };
+            for issue in &report.issues {
+                reporter.lint_issue(issue);
+            }
+            reporter.close_output();
+
+            if args.fix && !report.is_clean() {
+                // 有无法修复的问题时不改写清单, 否则这些行中的条目会被丢弃
+                if !report.is_fixable() {
+                    log_stderr!("哈希文件中有无法自动修复的问题, 没有改写哈希文件");
+                    exit(1);
+                }
+                // 与刷新一样不能删掉中断的生成或更新留下的日志
+                let (journal, replayed) = open_journal(&args, report.fixed.algorithm());
+                if !replayed.is_empty() {
+                    log_stderr!(
+                        "预写日志[{}]中有上次中断的运行留下的 {} 个哈希, 请先用更新模式 (-u) 完成恢复再修复",
+                        journal.path().display(),
+                        replayed.len()
+                    );
+                    exit(1);
+                }
+                write_manifest(&store, &report.fixed);
+                commit_journal(journal);
+                reporter.fixed(&args.hash_file_path, report.issues.len());
+            } else if !report.is_clean() {
+                exit(1);
+            }
+        }
+        Model::Cleanup => {
+            // 持有锁时没有其他进程在改写这个清单, 留下的临时文件和日志都来自已经结束的运行
+            let _lock = lock_manifest(&store);
+            for file_path in stale_artifacts(&args.hash_file_path) {
+                if let Err(err) = fs::remove_file(&file_path) {
+                    log_stderr!("清理[{}]时出现错误: {}", file_path.display(), err);
+                    exit(1);
+                }
+                reporter.pruned(&file_path);
+            }
+            reporter.close_output();
         }
     }
 }
@@ -70,139 +439,1457 @@ async fn main() {
 enum Model {
     Generate,
     Check,
+    Remote,
+    Update,
+    Diff,
+    Watch,
+    Lint,
+    Cleanup,
 }
 
 struct Args<'a> {
     model: Model,
     folder_path: &'a Path,
-    hash_file_path: &'a Path,
+    hash_file_path: PathBuf,
+    hash_file_template: &'a str,
+    keep_last: Option<usize>,
+    keep_days: Option<u64>,
+    against: Option<&'a Path>,
+    verifier: Verifier,
+    jobs: usize,
+    buffer_size: usize,
+    min_free_memory: Option<u64>,
+    max_rss: Option<u64>,
+    find_dupes: bool,
+    verbose: bool,
+    no_timestamps: bool,
+    also_emit: Vec<CompanionFormat>,
+    format: Option<ManifestFormat>,
+    algorithm: Option<&'a str>,
+    walk_options: WalkOptions,
+    json: bool,
+    progress: bool,
+    stdin_paths: bool,
+    group_by_dir: bool,
+    content_types: bool,
+    sort_order: Option<SortOrder>,
+    fix: bool,
+    rclone: Option<&'a str>,
+    outside_root: Option<OutsideRoot>,
+    strict_paths: bool,
+    output_queue: usize,
+    drop_when_full: bool,
+    mtime_tolerance: u64,
+    size_only: bool,
+    refresh: bool,
+    companion_policy: Option<CompanionPolicy>,
+    max_files: Option<usize>,
+    max_bytes: Option<u64>,
+    run_id: String,
 }
 
 impl Args<'_> {
-    fn parse_args(args: &[String]) -> io::Result<Args> {
+    fn parse_args(args: &[String]) -> io::Result<Args<'_>> {
         let model = match args.get(1) {
             Some(model) => match model.as_str() {
                 "-g" => Model::Generate,
                 "-c" => Model::Check,
-                _ => {
-                    return Err(io::Error::new(
-                        ErrorKind::Other,
-                        format!("不支持的模式: {}", model),
-                    ))
-                }
+                "-r" => Model::Remote,
+                "-u" => Model::Update,
+                "-d" => Model::Diff,
+                "-w" => Model::Watch,
+                "-l" => Model::Lint,
+                "-x" => Model::Cleanup,
+                _ => return Err(io::Error::other(format!("不支持的模式: {}", model))),
             },
-            None => return Err(io::Error::new(ErrorKind::Other, "缺少模式参数")),
-        };
-        let folder_path = match args.get(2) {
-            Some(folder_path) => Path::new(folder_path),
-            None => return Err(io::Error::new(ErrorKind::Other, "缺少文件夹路径参数")),
+            None => return Err(io::Error::other("缺少模式参数")),
         };
-        let hash_file_path = match args.get(3) {
-            Some(hash_file_path) => Path::new(hash_file_path),
-            None => return Err(io::Error::new(ErrorKind::Other, "缺少哈希文件路径参数")),
+        // 检查和清理模式只需要清单路径, 条目相对于清单所在的文件夹
+        let (folder_path, hash_file_path, first_option) =
+            if matches!(model, Model::Lint | Model::Cleanup) {
+                match args.get(2) {
+                    Some(hash_file_path) => (
+                        Path::new(hash_file_path).parent().unwrap_or(Path::new("")),
+                        hash_file_path,
+                        3,
+                    ),
+                    None => return Err(io::Error::other("缺少哈希文件路径参数")),
+                }
+            } else {
+                let folder_path = match args.get(2) {
+                    Some(folder_path) => Path::new(folder_path),
+                    None => return Err(io::Error::other("缺少文件夹路径参数")),
+                };
+                match args.get(3) {
+                    Some(hash_file_path) => (folder_path, hash_file_path, 4),
+                    None => return Err(io::Error::other("缺少哈希文件路径参数")),
+                }
+            };
+
+        let mut also_emit = Vec::new();
+        let mut format = None;
+        let mut algorithm = None;
+        let mut against = None;
+        let mut verifier = Verifier::new();
+        let mut volatile = false;
+        let mut jobs = 16;
+        let mut buffer_size = DEFAULT_BUFFER_SIZE;
+        let mut min_free_memory = None;
+        let mut max_rss = None;
+        let mut json = false;
+        let mut find_dupes = false;
+        let mut verbose = false;
+        let mut no_timestamps = false;
+        let mut keep_last = None;
+        let mut keep_days = None;
+        let mut progress = false;
+        let mut stdin_paths = false;
+        let mut group_by_dir = false;
+        let mut content_types = false;
+        let mut sort_order = None;
+        let mut fix = false;
+        let mut rclone = None;
+        let mut outside_root = None;
+        let mut strict_paths = false;
+        let mut output_queue = None;
+        let mut mtime_tolerance = None;
+        let mut size_only = false;
+        let mut assert_readonly = false;
+        let mut refresh = false;
+        let mut companion_policy: Option<CompanionPolicy> = None;
+        let mut max_files = None;
+        let mut max_bytes = None;
+        let mut drop_when_full = false;
+        let mut walk_options = WalkOptions::new();
+        let mut options = args.iter().skip(first_option);
+        while let Some(option) = options.next() {
+            match option.as_str() {
+                "--also-emit" => {
+                    let formats = match options.next() {
+                        Some(formats) => formats,
+                        None => return Err(io::Error::other("--also-emit 缺少格式列表")),
+                    };
+                    for format in formats.split(',') {
+                        also_emit
+                            .push(CompanionFormat::from_name(format).map_err(io::Error::other)?);
+                    }
+                }
+                "--format" => {
+                    let name = match options.next() {
+                        Some(name) => name,
+                        None => return Err(io::Error::other("--format 缺少格式名称")),
+                    };
+                    format = Some(ManifestFormat::from_name(name).map_err(io::Error::other)?);
+                }
+                "--algo" => match options.next() {
+                    Some(name) => algorithm = Some(name.as_str()),
+                    None => return Err(io::Error::other("--algo 缺少算法名称")),
+                },
+                "--against" => match options.next() {
+                    Some(path) => against = Some(Path::new(path)),
+                    None => return Err(io::Error::other("--against 缺少哈希文件路径")),
+                },
+                "--jobs" => {
+                    jobs = match options.next().map(|jobs| jobs.parse()) {
+                        Some(Ok(jobs)) if jobs > 0 => jobs,
+                        Some(_) => return Err(io::Error::other("--jobs 必须是正整数")),
+                        None => return Err(io::Error::other("--jobs 缺少任务数量")),
+                    };
+                }
+                "--buffer-size" => {
+                    buffer_size = match options.next().map(|size| parse_size(size)) {
+                        Some(Some(size)) if size > 0 => size,
+                        Some(_) => {
+                            return Err(io::Error::other(
+                                "--buffer-size 必须是正整数, 可以带K或M后缀",
+                            ))
+                        }
+                        None => return Err(io::Error::other("--buffer-size 缺少缓冲区大小")),
+                    };
+                }
+                "--min-free-mem" => {
+                    min_free_memory = match options.next().map(|size| parse_size(size)) {
+                        Some(Some(size)) => Some(size as u64),
+                        Some(None) => {
+                            return Err(io::Error::other(
+                                "--min-free-mem 必须是整数, 可以带K, M或G后缀",
+                            ))
+                        }
+                        None => return Err(io::Error::other("--min-free-mem 缺少内存大小")),
+                    };
+                }
+                "--max-rss" => {
+                    max_rss = match options.next().map(|size| parse_size(size)) {
+                        Some(Some(size)) => Some(size as u64),
+                        Some(None) => {
+                            return Err(io::Error::other("--max-rss 必须是整数, 可以带K, M或G后缀"))
+                        }
+                        None => return Err(io::Error::other("--max-rss 缺少内存大小")),
+                    };
+                }
+                "--volatile" => {
+                    let pattern = match options.next() {
+                        Some(pattern) => pattern,
+                        None => return Err(io::Error::other("--volatile 缺少匹配模式")),
+                    };
+                    verifier = verifier.volatile(pattern).map_err(io::Error::other)?;
+                    volatile = true;
+                }
+                "--include" | "--exclude" => {
+                    let pattern = match options.next() {
+                        Some(pattern) => pattern,
+                        None => return Err(io::Error::other(format!("{} 缺少匹配模式", option))),
+                    };
+                    walk_options = if option == "--include" {
+                        walk_options.include(pattern)
+                    } else {
+                        walk_options.exclude(pattern)
+                    }
+                    .map_err(io::Error::other)?;
+                }
+                "--json" => json = true,
+                "--find-dupes" => find_dupes = true,
+                "--keep-last" => {
+                    keep_last = match options.next().map(|count| count.parse()) {
+                        Some(Ok(count)) if count > 0 => Some(count),
+                        Some(_) => return Err(io::Error::other("--keep-last 必须是正整数")),
+                        None => return Err(io::Error::other("--keep-last 缺少保留数量")),
+                    };
+                }
+                "--keep-days" => {
+                    keep_days = match options.next().map(|days| days.parse()) {
+                        Some(Ok(days)) => Some(days),
+                        Some(Err(_)) => return Err(io::Error::other("--keep-days 必须是整数")),
+                        None => return Err(io::Error::other("--keep-days 缺少保留天数")),
+                    };
+                }
+                "--verbose" => verbose = true,
+                "--no-timestamps" => no_timestamps = true,
+                "--progress" => progress = true,
+                "--stdin-paths" => stdin_paths = true,
+                "--content-types" => content_types = true,
+                "--fix" => fix = true,
+                "--outside-root" => {
+                    let name = match options.next() {
+                        Some(name) => name,
+                        None => return Err(io::Error::other("--outside-root 缺少处理方式")),
+                    };
+                    outside_root = Some(OutsideRoot::from_name(name).map_err(io::Error::other)?);
+                }
+                "--rclone" => match options.next() {
+                    Some(hash_type) => rclone = Some(hash_type.as_str()),
+                    None => return Err(io::Error::other("--rclone 缺少哈希类型")),
+                },
+                "--sort" => {
+                    let name = match options.next() {
+                        Some(name) => name,
+                        None => return Err(io::Error::other("--sort 缺少排序方式")),
+                    };
+                    sort_order = Some(SortOrder::from_name(name).map_err(io::Error::other)?);
+                }
+                "--group-by" => match options.next().map(|key| key.as_str()) {
+                    Some("dir") => group_by_dir = true,
+                    Some(key) => {
+                        return Err(io::Error::other(format!(
+                            "--group-by 不支持的分组方式: {}",
+                            key
+                        )))
+                    }
+                    None => return Err(io::Error::other("--group-by 缺少分组方式")),
+                },
+                "--skip-symlinks" => walk_options = walk_options.follow_symlinks(false),
+                "--skip-hidden" => walk_options = walk_options.skip_hidden(true),
+                "--strict-paths" => strict_paths = true,
+                "--mtime-tolerance" => {
+                    mtime_tolerance = match options.next().map(|seconds| seconds.parse()) {
+                        Some(Ok(seconds)) => Some(seconds),
+                        Some(Err(_)) => {
+                            return Err(io::Error::other("--mtime-tolerance 必须是整数秒数"))
+                        }
+                        None => return Err(io::Error::other("--mtime-tolerance 缺少秒数")),
+                    };
+                }
+                "--size-only" => size_only = true,
+                "--output-queue" => {
+                    output_queue = match options.next().map(|lines| lines.parse()) {
+                        Some(Ok(lines)) if lines > 0 => Some(lines),
+                        Some(_) => return Err(io::Error::other("--output-queue 必须是正整数")),
+                        None => return Err(io::Error::other("--output-queue 缺少队列长度")),
+                    };
+                }
+                "--on-full" => match options.next().map(|policy| policy.as_str()) {
+                    Some("block") => drop_when_full = false,
+                    Some("drop") => drop_when_full = true,
+                    Some(policy) => {
+                        return Err(io::Error::other(format!(
+                            "--on-full 不支持的处理方式: {}",
+                            policy
+                        )))
+                    }
+                    None => return Err(io::Error::other("--on-full 缺少处理方式")),
+                },
+                "--strict-walk" => walk_options = walk_options.strict(true),
+                "--assert-readonly" => assert_readonly = true,
+                "--refresh" => refresh = true,
+                "--max-files" => {
+                    max_files = match options.next().map(|files| files.parse()) {
+                        Some(Ok(files)) => Some(files),
+                        Some(Err(_)) => return Err(io::Error::other("--max-files 必须是非负整数")),
+                        None => return Err(io::Error::other("--max-files 缺少文件数量")),
+                    };
+                }
+                "--max-bytes" => {
+                    max_bytes = match options.next().map(|size| parse_size(size)) {
+                        Some(Some(size)) => Some(size as u64),
+                        Some(None) => {
+                            return Err(io::Error::other(
+                                "--max-bytes 必须是整数, 可以带K, M或G后缀",
+                            ))
+                        }
+                        None => return Err(io::Error::other("--max-bytes 缺少字节数")),
+                    };
+                }
+                "--also-emit-for" => {
+                    let pattern = match options.next() {
+                        Some(pattern) => pattern,
+                        None => return Err(io::Error::other("--also-emit-for 缺少匹配模式")),
+                    };
+                    companion_policy = Some(
+                        companion_policy
+                            .unwrap_or_default()
+                            .pattern(pattern)
+                            .map_err(io::Error::other)?,
+                    );
+                }
+                "--also-emit-over" => {
+                    let min_size = match options.next().map(|size| parse_size(size)) {
+                        Some(Some(size)) => size as u64,
+                        Some(None) => {
+                            return Err(io::Error::other(
+                                "--also-emit-over 必须是整数, 可以带K, M或G后缀",
+                            ))
+                        }
+                        None => return Err(io::Error::other("--also-emit-over 缺少文件大小")),
+                    };
+                    companion_policy =
+                        Some(companion_policy.unwrap_or_default().min_size(min_size));
+                }
+                _ => return Err(io::Error::other(format!("不支持的选项: {}", option))),
+            }
+        }
+        if no_timestamps && matches!(model, Model::Check | Model::Remote | Model::Diff) {
+            return Err(io::Error::other(
+                "--no-timestamps 不能在校验和比较模式下使用",
+            ));
+        }
+        if (keep_last.is_some() || keep_days.is_some()) && !matches!(model, Model::Generate) {
+            return Err(io::Error::other(
+                "--keep-last 和 --keep-days 只能在生成模式下使用",
+            ));
+        }
+        if find_dupes && !matches!(model, Model::Generate) {
+            return Err(io::Error::other("--find-dupes 只能在生成模式下使用"));
+        }
+        if !also_emit.is_empty() && !matches!(model, Model::Generate) {
+            return Err(io::Error::other("--also-emit 只能在生成模式下使用"));
+        }
+        if algorithm.is_some() && !matches!(model, Model::Generate | Model::Watch) {
+            return Err(io::Error::other(
+                "--algo 只能在生成和监视模式下使用, 其他模式使用清单中记录的算法",
+            ));
+        }
+        if format.is_some() && matches!(model, Model::Check | Model::Remote | Model::Diff) {
+            return Err(io::Error::other("--format 只能在生成和更新模式下使用"));
+        }
+        if volatile && !matches!(model, Model::Check | Model::Remote) {
+            return Err(io::Error::other("--volatile 只能在校验模式下使用"));
+        }
+        if against.is_some() && !matches!(model, Model::Diff) {
+            return Err(io::Error::other("--against 只能在比较模式下使用"));
+        }
+        if group_by_dir && !matches!(model, Model::Check | Model::Remote) {
+            return Err(io::Error::other("--group-by 只能在校验模式下使用"));
+        }
+        if (min_free_memory.is_some() || max_rss.is_some()) && matches!(model, Model::Diff) {
+            return Err(io::Error::other(
+                "--min-free-mem 和 --max-rss 不能在比较模式下使用",
+            ));
+        }
+        if content_types && !matches!(model, Model::Generate | Model::Check | Model::Remote) {
+            return Err(io::Error::other(
+                "--content-types 只能在生成和校验模式下使用",
+            ));
+        }
+        if stdin_paths && !matches!(model, Model::Generate | Model::Check) {
+            return Err(io::Error::other("--stdin-paths 只能在生成和校验模式下使用"));
+        }
+        if fix && !matches!(model, Model::Lint) {
+            return Err(io::Error::other("--fix 只能在检查模式下使用"));
+        }
+        if outside_root.is_some()
+            && !matches!(model, Model::Generate | Model::Update | Model::Watch)
+        {
+            return Err(io::Error::other(
+                "--outside-root 只能在生成, 更新和监视模式下使用",
+            ));
+        }
+        if strict_paths
+            && !matches!(
+                model,
+                Model::Check | Model::Remote | Model::Update | Model::Diff
+            )
+        {
+            return Err(io::Error::other(
+                "--strict-paths 只能在校验, 更新和比较模式下使用",
+            ));
+        }
+        if (output_queue.is_some() || drop_when_full) && !(json || stdin_paths) {
+            return Err(io::Error::other(
+                "--output-queue 和 --on-full 只能和 --json 一起使用",
+            ));
+        }
+        if (mtime_tolerance.is_some() || size_only)
+            && !matches!(model, Model::Update | Model::Watch | Model::Diff)
+        {
+            return Err(io::Error::other(
+                "--mtime-tolerance 和 --size-only 只能在更新, 监视和比较模式下使用",
+            ));
+        }
+        if rclone.is_some() && !matches!(model, Model::Check) {
+            return Err(io::Error::other("--rclone 只能在校验模式下使用"));
+        }
+
+        if companion_policy.is_some() && also_emit.is_empty() {
+            return Err(io::Error::other(
+                "--also-emit-for 和 --also-emit-over 只能和 --also-emit 一起使用",
+            ));
+        }
+        if (max_files.is_some() || max_bytes.is_some())
+            && (!matches!(model, Model::Check) || refresh || stdin_paths)
+        {
+            return Err(io::Error::other(
+                "--max-files 和 --max-bytes 只能在校验模式下使用, 并且不能和 --refresh 或 --stdin-paths 一起使用",
+            ));
+        }
+        if refresh && !matches!(model, Model::Check) {
+            return Err(io::Error::other("--refresh 只能在校验模式下使用"));
+        }
+        if refresh && (stdin_paths || rclone.is_some() || volatile || assert_readonly) {
+            return Err(io::Error::other(
+                "--refresh 不能和 --stdin-paths, --rclone, --volatile 或 --assert-readonly 一起使用",
+            ));
+        }
+
+        // 只读模式下只允许不写入任何文件的模式, 并且清单不能位于被校验的文件夹中
+        if assert_readonly {
+            if !matches!(model, Model::Check | Model::Remote | Model::Diff) {
+                return Err(io::Error::other(
+                    "--assert-readonly 只能在校验和比较模式下使用",
+                ));
+            }
+            let mut file_paths = vec![Path::new(hash_file_path)];
+            file_paths.extend(against);
+            check_readonly(folder_path, &file_paths)?;
+        }
+
+        // 生成模式下展开哈希文件路径中的模板
+        let hash_file_template = hash_file_path.as_str();
+        let hash_file_path = if matches!(model, Model::Generate) {
+            PathBuf::from(
+                expand_template(hash_file_template, folder_path, SystemTime::now())
+                    .map_err(io::Error::other)?,
+            )
+        } else {
+            PathBuf::from(hash_file_path)
         };
+
+        // 清单位于文件夹中时, 不把清单本身和锁文件等当作普通文件记录
+        for file_path in sidecar_paths(&hash_file_path) {
+            walk_options = walk_options.exclude_path(&file_path);
+        }
+
+        let mut verifier = verifier.jobs(jobs).buffer_size(buffer_size);
+        if let Some(min_free_memory) = min_free_memory {
+            verifier = verifier.min_free_memory(min_free_memory);
+        }
+        if let Some(max_rss) = max_rss {
+            verifier = verifier.max_rss(max_rss);
+        }
+
         Ok(Args {
             model,
             folder_path,
             hash_file_path,
+            hash_file_template,
+            keep_last,
+            keep_days,
+            against,
+            verifier,
+            jobs,
+            buffer_size,
+            min_free_memory,
+            max_rss,
+            find_dupes,
+            verbose,
+            no_timestamps,
+            also_emit,
+            format,
+            algorithm,
+            walk_options,
+            // 从标准输入读取路径时总是逐行输出JSON结果
+            json: json || stdin_paths,
+            progress,
+            stdin_paths,
+            group_by_dir,
+            content_types,
+            sort_order,
+            fix,
+            rclone,
+            outside_root,
+            strict_paths,
+            output_queue: output_queue.unwrap_or(DEFAULT_OUTPUT_QUEUE),
+            drop_when_full,
+            mtime_tolerance: mtime_tolerance.unwrap_or(0),
+            size_only,
+            refresh,
+            companion_policy,
+            max_files,
+            max_bytes,
+            run_id: RUN_ID.clone(),
         })
     }
 }
 
-async fn request_task_permit(task_semaphore: &Semaphore) -> SemaphorePermit<'_> {
-    match task_semaphore.acquire().await {
-        Ok(permit) => permit,
+// 解析带K或M后缀的字节数
+fn parse_size(size: &str) -> Option<usize> {
+    let (number, unit) = match size.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => size.split_at(i),
+        None => (size, ""),
+    };
+    let unit = match unit {
+        "" => 1,
+        "K" | "k" | "KiB" => 1024,
+        "M" | "m" | "MiB" => 1024 * 1024,
+        "G" | "g" | "GiB" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    number.parse::<usize>().ok()?.checked_mul(unit)
+}
+
+async fn watch(args: &Args<'_>, store: &TextFileStore, reporter: &mut Reporter) {
+    // 哈希文件不存在时从空清单开始, 第一次更新会计算所有文件的哈希
+    let mut manifest = match store.load(args.folder_path) {
+        Ok(manifest) => manifest,
+        Err(err) if err.is_not_found() => Manifest::new(
+            args.folder_path,
+            args.algorithm.unwrap_or(DEFAULT_ALGORITHM),
+        ),
+        Err(err) => {
+            log_stderr!("读取哈希值时出现错误: {}", err);
+            exit(1)
+        }
+    };
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = match notify::recommended_watcher(move |event| {
+        let _ = tx.send(event);
+    }) {
+        Ok(watcher) => watcher,
+        Err(err) => {
+            log_stderr!("创建文件监视器时出现错误: {}", err);
+            exit(1)
+        }
+    };
+    if let Err(err) = watcher.watch(args.folder_path, RecursiveMode::Recursive) {
+        log_stderr!("监视文件夹时出现错误: {}", err);
+        exit(1);
+    }
+
+    loop {
+        // 与更新模式一样先把哈希写入预写日志, 上次中断或失败的更新已经计算出的哈希直接复用
+        let (journal, replayed) = open_journal(args, manifest.algorithm());
+        reporter.resumed(journal.path(), replayed.len());
+        for (file_path, record) in replayed.iter() {
+            manifest.insert(file_path.clone(), record.clone());
+        }
+
+        // 只重新计算改变文件的哈希, 有变化时才改写清单
+        let mut changed = !replayed.is_empty();
+        let result = hash_generator(args)
+            .journal(Arc::clone(&journal))
+            .update(&manifest, |event| {
+                if let Event::Added(_) | Event::Changed(_) | Event::Removed(_) = event {
+                    changed = true;
+                }
+                reporter.handle(event)
+            })
+            .await;
+        match result {
+            Ok(new_manifest) => {
+                manifest = new_manifest;
+                if changed {
+                    apply_output_options(&mut manifest, args);
+                    write_manifest(store, &manifest);
+                }
+                commit_journal(journal);
+            }
+            // 文件可能在计算哈希时被删除, 保留日志, 等待下一次变化后重试
+            Err(err) => log_stderr!("更新哈希时出现错误: {}", err),
+        }
+
+        // 等待文件变化
+        loop {
+            tokio::select! {
+                event = rx.recv() => match event {
+                    Some(Ok(event)) if is_relevant_change(&event, &args.walk_options) => break,
+                    Some(Ok(_)) => {}
+                    Some(Err(err)) => log_stderr!("警告: {}", err),
+                    None => return,
+                },
+                _ = tokio::signal::ctrl_c() => return,
+            }
+        }
+
+        // 合并短时间内连续发生的变化
+        while let Ok(Some(_)) = tokio::time::timeout(Duration::from_millis(500), rx.recv()).await {}
+    }
+}
+
+// 忽略只读取文件的事件, 以及清单和它旁边的锁文件等的变化
+// 清单位于被监视的文件夹中时每次写入清单都会产生事件, 不忽略的话会不停地更新
+fn is_relevant_change(event: &notify::Event, walk_options: &WalkOptions) -> bool {
+    !matches!(event.kind, EventKind::Access(_))
+        && (event.paths.is_empty()
+            || event
+                .paths
+                .iter()
+                .any(|path| !walk_options.is_excluded_path(path)))
+}
+
+// 每行是 "子目录 清单地址", 忽略空行和以#开头的注释
+fn read_mappings(path: &Path) -> io::Result<Vec<(String, String)>> {
+    let mut mappings = Vec::new();
+    for line in fs::read_to_string(path)?.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.rsplit_once(char::is_whitespace) {
+            Some((sub_folder, url)) => {
+                mappings.push((sub_folder.trim_end().to_string(), url.to_string()))
+            }
+            None => return Err(io::Error::other(format!("无法解析: {}", line))),
+        }
+    }
+    Ok(mappings)
+}
+
+// 从标准输入读取下一个路径, 相对路径按文件夹路径解析, 输入结束时返回 None
+async fn next_stdin_path(
+    lines: &mut Lines<BufReader<Stdin>>,
+    folder_path: &Path,
+) -> Option<PathBuf> {
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => return None,
+            Err(err) => {
+                log_stderr!("读取标准输入时出现错误: {}", err);
+                exit(1)
+            }
+        };
+        let line = line.trim_end_matches('\r');
+        if !line.is_empty() {
+            return Some(folder_path.join(line));
+        }
+    }
+}
+
+// 边读取标准输入边计算哈希, 所有路径共用一个工作任务池, 输入结束后返回包含所有文件的清单
+async fn hash_stdin_paths(
+    generator: &HashGenerator,
+    args: &Args<'_>,
+    reporter: &mut Reporter,
+) -> Result<Manifest, Error> {
+    let (path_tx, path_rx) = mpsc::channel(64);
+    let folder_path = args.folder_path.to_path_buf();
+    let stdin_reader = tokio::spawn(async move {
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        while let Some(file_path) = next_stdin_path(&mut lines, &folder_path).await {
+            if path_tx.send(file_path).await.is_err() {
+                break;
+            }
+        }
+    });
+    let result = generator
+        .run_paths_stream(args.folder_path, path_rx, |event| reporter.handle(event))
+        .await;
+    stdin_reader.abort();
+    result
+}
+
+// 边读取标准输入边校验, 所有路径共用一个工作任务池, 清单中没有的文件算作校验失败
+async fn verify_stdin_paths(
+    manifest: &Manifest,
+    args: &Args<'_>,
+    reporter: &mut Reporter,
+) -> Result<VerifyReport, Error> {
+    let (path_tx, path_rx) = mpsc::channel(64);
+    let folder_path = args.folder_path.to_path_buf();
+    let stdin_reader = tokio::spawn(async move {
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        while let Some(file_path) = next_stdin_path(&mut lines, &folder_path).await {
+            if path_tx.send(file_path).await.is_err() {
+                break;
+            }
+        }
+    });
+    let result = args
+        .verifier
+        .run_paths_stream(manifest, path_rx, |event| reporter.handle(event))
+        .await;
+    stdin_reader.abort();
+    result
+}
+
+// 按解析符号链接后的真实路径判断清单是否位于文件夹中
+fn check_readonly(folder_path: &Path, file_paths: &[&Path]) -> io::Result<()> {
+    let canonicalize = |path: &Path| {
+        fs::canonicalize(path)
+            .map_err(|err| io::Error::other(format!("无法解析路径[{}]: {}", path.display(), err)))
+    };
+    let folder_path = canonicalize(folder_path)?;
+    for file_path in file_paths {
+        if canonicalize(file_path)?.starts_with(&folder_path) {
+            return Err(io::Error::other(format!(
+                "--assert-readonly 要求清单[{}]位于文件夹[{}]之外",
+                file_path.display(),
+                folder_path.display()
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn hash_generator(args: &Args) -> HashGenerator {
+    let generator = HashGenerator::new()
+        .jobs(args.jobs)
+        .buffer_size(args.buffer_size)
+        .walk_options(args.walk_options.clone())
+        .mtime_tolerance(args.mtime_tolerance)
+        .size_only(args.size_only);
+    let generator = match args.min_free_memory {
+        Some(min_free_memory) => generator.min_free_memory(min_free_memory),
+        None => generator,
+    };
+    match args.max_rss {
+        Some(max_rss) => generator.max_rss(max_rss),
+        None => generator,
+    }
+}
+
+fn lock_manifest(store: &TextFileStore) -> ManifestLock {
+    match store.lock() {
+        Ok(lock) => lock,
         Err(err) => {
-            eprintln!("获取任务信号量时出现错误: {}", err);
-            exit(1);
+            log_stderr!("锁定哈希文件时出现错误: {}", err);
+            exit(1)
         }
     }
 }
 
-async fn await_all_async_tasks(handles: Vec<JoinHandle<()>>) {
-    for handle in handles {
-        if let Err(err) = handle.await {
-            eprintln!("等待异步任务完成时出现错误: {}", err);
-            exit(1);
+fn open_journal(args: &Args, algorithm: &str) -> (Arc<Journal>, Manifest) {
+    match Journal::open(&args.hash_file_path, args.folder_path, algorithm) {
+        Ok((journal, replayed)) => (Arc::new(journal), replayed),
+        Err(err) => {
+            log_stderr!("打开预写日志时出现错误: {}", err);
+            exit(1)
         }
     }
 }
 
-fn model_check(args: Args, task_semaphore: Arc<Semaphore>) -> Vec<JoinHandle<()>> {
-    let hash_map = match read_hash_file(args.folder_path, args.hash_file_path) {
-        Ok(hash_map) => hash_map,
+// 清单已经写入磁盘后才删除日志
+fn commit_journal(journal: Arc<Journal>) {
+    // 调用前需要先丢弃持有日志的生成器
+    let journal = Arc::into_inner(journal).expect("预写日志仍在使用中");
+    if let Err(err) = journal.commit() {
+        log_stderr!("删除预写日志时出现错误: {}", err);
+        exit(1);
+    }
+}
+
+fn read_manifest(store: &dyn ManifestStore, args: &Args) -> Manifest {
+    match store.load(args.folder_path) {
+        Ok(manifest) => {
+            check_entry_paths(&manifest, args);
+            manifest
+        }
         Err(err) => {
-            eprintln!("读取哈希值时出现错误: {}", err);
+            log_stderr!("读取哈希值时出现错误: {}", err);
             exit(1)
         }
-    };
+    }
+}
+
+// 严格模式下拒绝包含绝对路径条目的清单
+fn check_entry_paths(manifest: &Manifest, args: &Args) {
+    if !args.strict_paths {
+        return;
+    }
+    if let Some(file_path) = manifest.absolute_entries().first() {
+        log_stderr!(
+            "读取哈希值时出现错误: 严格模式下不允许绝对路径的条目[{}]",
+            file_path.display()
+        );
+        exit(1);
+    }
+}
+
+fn read_rclone_manifest(args: &Args, hash_type: &str) -> Manifest {
+    let result = fs::File::open(&args.hash_file_path)
+        .map_err(|err| err.to_string())
+        .and_then(|file| {
+            Manifest::from_rclone(args.folder_path, hash_type, io::BufReader::new(file))
+                .map_err(|err| err.to_string())
+        });
+    match result {
+        Ok(manifest) => manifest,
+        Err(err) => {
+            log_stderr!("读取哈希值时出现错误: {}", err);
+            exit(1)
+        }
+    }
+}
 
-    let mut handles = Vec::new();
+fn apply_output_options(manifest: &mut Manifest, args: &Args) {
+    if let Some(format) = args.format {
+        manifest.set_format(format);
+    }
+    if args.no_timestamps {
+        manifest.set_timestamps(false);
+    }
+    manifest.set_run_id(&args.run_id);
+    if let Some(sort_order) = args.sort_order {
+        manifest.set_sort_order(sort_order);
+    }
+    if let Some(outside_root) = args.outside_root {
+        manifest.set_outside_root(outside_root);
+    }
+}
 
-    for (file_path, hash) in hash_map {
-        let task_semaphore = Arc::clone(&task_semaphore);
+fn write_manifest(store: &dyn ManifestStore, manifest: &Manifest) {
+    // 不在文件夹中的条目按策略处理, 设置为报错时由写入清单时的错误说明
+    let action = match manifest.outside_root() {
+        OutsideRoot::Absolute => "以绝对路径记录",
+        OutsideRoot::Skip => "已跳过",
+        _ => "",
+    };
+    if !action.is_empty() {
+        for file_path in manifest.outside_root_paths() {
+            log_stderr!(
+                "警告: [{}]不在文件夹[{}]中, {}",
+                file_path.display(),
+                manifest.folder_path().display(),
+                action
+            );
+        }
+    }
+    if let Err(err) = store.save(manifest) {
+        log_stderr!("写入哈希到文件时出现错误: {}", err);
+        exit(1);
+    }
+}
 
-        let handle = tokio::spawn(async move {
-            let permit = request_task_permit(&task_semaphore).await;
+struct Reporter {
+    json: bool,
+    verbose: bool,
+    show_progress: bool,
+    progress_rendered: bool,
+    progress: Progress,
+    last_render: Instant,
+    counts: BTreeMap<&'static str, usize>,
+    group_root: Option<PathBuf>,
+    groups: BTreeMap<String, GroupSummary>,
+    content_types: Option<BTreeMap<&'static str, (usize, u64)>>,
+    anomalies: AnomalyDetector,
+    sort_order: SortOrder,
+    run_id: String,
+    output: Option<JsonOutput>,
+    drop_when_full: bool,
+    dropped: usize,
+    stop_on_failure: bool,
+    // 部分校验时已选中的文件数和清单中的文件总数
+    partial: Option<(usize, usize)>,
+}
 
-            match compute_hash(&file_path).await {
-                Ok(hash_new) => {
-                    if hash == hash_new {
-                        println!("[{} | 成功]", file_path.display());
-                    } else {
-                        println!("[{} | 失败]", file_path.display());
-                        exit(0);
-                    }
-                }
-                Err(err) => {
-                    if err.kind() == ErrorKind::NotFound {
-                        println!("[{} | 缺失]", file_path.display());
-                        exit(0);
-                    } else {
-                        println!("计算[{}]的哈希时出现错误: {}", file_path.display(), err);
-                        exit(1);
-                    }
+// 输出线程从有界队列中取出JSON行写到标准输出, 消费方较慢时不会无限占用内存
+struct JsonOutput {
+    sender: Option<SyncSender<String>>,
+    writer: Option<thread::JoinHandle<()>>,
+}
+
+impl JsonOutput {
+    fn start(capacity: usize) -> JsonOutput {
+        let (sender, receiver) = sync_channel::<String>(capacity);
+        let writer = thread::spawn(move || {
+            for line in receiver {
+                // 消费方已经关闭时停止写入, 之后发送的行都被丢弃
+                if writeln!(io::stdout(), "{}", line).is_err() {
+                    break;
                 }
             }
-            drop(permit);
         });
-        handles.push(handle);
+        JsonOutput {
+            sender: Some(sender),
+            writer: Some(writer),
+        }
+    }
+}
+
+// 关闭队列并等待输出线程写完剩余的行
+impl Drop for JsonOutput {
+    fn drop(&mut self) {
+        self.sender = None;
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
     }
-    handles
 }
 
-fn model_generate(
-    file_paths: &Arc<Vec<PathBuf>>,
-    task_semaphore: Arc<Semaphore>,
-) -> (Receiver<(PathBuf, u128)>, Vec<JoinHandle<()>>) {
-    let (tx, rx) = bounded(64);
-    let tx = Arc::new(tx);
+// 一个顶层目录的校验结果统计
+#[derive(Default)]
+struct GroupSummary {
+    counts: BTreeMap<&'static str, usize>,
+    bytes: u64,
+}
 
-    let mut handles = Vec::new();
+impl GroupSummary {
+    fn count(&self, status: &str) -> usize {
+        self.counts.get(status).copied().unwrap_or(0)
+    }
 
-    for file_path in file_paths.iter().cloned() {
-        let tx = Arc::clone(&tx);
-        let task_semaphore = Arc::clone(&task_semaphore);
+    fn has_problems(&self) -> bool {
+        self.count("failed") > 0 || self.count("missing") > 0
+    }
+}
 
-        let handle = tokio::spawn(async move {
-            let permit = request_task_permit(&task_semaphore).await;
+impl Reporter {
+    fn new(args: &Args) -> Reporter {
+        Reporter {
+            json: args.json,
+            verbose: args.verbose,
+            // 只在交互式终端中显示进度
+            show_progress: args.progress && io::stderr().is_terminal(),
+            progress_rendered: false,
+            progress: Progress::new(),
+            last_render: Instant::now(),
+            counts: BTreeMap::new(),
+            group_root: args.group_by_dir.then(|| args.folder_path.to_path_buf()),
+            groups: BTreeMap::new(),
+            content_types: args.content_types.then(BTreeMap::new),
+            anomalies: AnomalyDetector::new(),
+            sort_order: args.sort_order.unwrap_or_default(),
+            run_id: args.run_id.clone(),
+            output: args.json.then(|| JsonOutput::start(args.output_queue)),
+            drop_when_full: args.drop_when_full,
+            dropped: 0,
+            // 刷新清单和校验多个上游清单时要处理完所有文件
+            stop_on_failure: !args.refresh && !matches!(args.model, Model::Remote),
+            partial: None,
+        }
+    }
 
-            match compute_hash(&file_path).await {
-                Ok(hash) => {
-                    println!("[{} | {:x}]", file_path.display(), hash);
-                    if let Err(err) = tx.send((file_path, hash)) {
-                        eprintln!("发送哈希到通道时出现错误: {}", err);
-                        exit(1)
+    fn handle(&mut self, event: Event) {
+        self.progress.record(&event);
+        self.anomalies.record(&event);
+        if let Some(status) = event_status(&event) {
+            *self.counts.entry(status).or_insert(0) += 1;
+        }
+        if let Some(content_types) = &mut self.content_types {
+            if let Event::Hashed {
+                content_type,
+                bytes,
+                ..
+            }
+            | Event::Verified {
+                content_type: Some(content_type),
+                bytes,
+                ..
+            } = event
+            {
+                let (files, total_bytes) = content_types.entry(content_type.name()).or_default();
+                *files += 1;
+                *total_bytes += bytes;
+            }
+        }
+
+        // 分组时文本输出只列出有问题的文件, 并且不在第一个问题处退出
+        if let (
+            Some(group_root),
+            Event::Verified {
+                file_path,
+                bytes,
+                status,
+                ..
+            },
+        ) = (&self.group_root, &event)
+        {
+            let group = top_level_dir(group_root, file_path);
+            let summary = self.groups.entry(group).or_default();
+            *summary
+                .counts
+                .entry(event_status(&event).unwrap_or_default())
+                .or_insert(0) += 1;
+            summary.bytes += bytes;
+            if !self.json {
+                if *status != VerifyStatus::Passed {
+                    self.clear_progress();
+                    println!("[{} | {}]", file_path.display(), status_text(*status));
+                }
+                if self.last_render.elapsed() >= Duration::from_millis(200) {
+                    self.render_progress();
+                }
+                return;
+            }
+        }
+
+        if self.json {
+            if let Some(line) = event_json(&event) {
+                self.clear_progress();
+                self.stream_json(line);
+            } else if let Event::Warning(err) = event {
+                self.clear_progress();
+                log_stderr!("警告: {}", err);
+            }
+        } else if self.verbose || !matches!(event, Event::Skipped { .. }) {
+            // 跳过的文件只在详细模式下输出
+            self.clear_progress();
+            print_event(event, self.stop_on_failure);
+        }
+
+        if self.last_render.elapsed() >= Duration::from_millis(200) {
+            self.render_progress();
+        }
+    }
+
+    fn render_progress(&mut self) {
+        if self.show_progress {
+            let progress = &self.progress;
+            let eta = match progress.eta() {
+                Some(eta) => format!("{}s", eta.as_secs()),
+                None => "-".to_string(),
+            };
+            eprint!(
+                "\r已完成 {}/{} 个文件, {:.1} MiB/s, 剩余时间 {}",
+                progress.done_files(),
+                progress.total_files(),
+                progress.bytes_per_sec() / 1048576.0,
+                eta
+            );
+            let _ = io::stderr().flush();
+            self.progress_rendered = true;
+            self.last_render = Instant::now();
+        }
+    }
+
+    fn clear_progress(&mut self) {
+        if self.progress_rendered {
+            eprint!("\r\x1b[2K");
+            self.progress_rendered = false;
+        }
+    }
+
+    fn finish(&mut self) {
+        self.clear_progress();
+        self.show_progress = false;
+        let anomalies = self.anomalies.findings();
+        if self.json {
+            for anomaly in &anomalies {
+                let mut line = json!({
+                    "anomaly": anomaly.name(),
+                    "message": anomaly.to_string(),
+                });
+                match anomaly {
+                    Anomaly::ManyEmpty { files, total }
+                    | Anomaly::ManyZeroFilled { files, total } => {
+                        line["files"] = json!(files);
+                        line["total"] = json!(total);
                     }
+                    Anomaly::WipedOnCheck { files } => line["files"] = json!(files),
+                    _ => {}
                 }
-                Err(err) => {
-                    eprintln!("计算[{}]的哈希时出现错误: {}", file_path.display(), err);
-                    exit(1);
+                self.print_json(line);
+            }
+            let mut summary = serde_json::Map::new();
+            summary.insert("files".to_string(), json!(self.progress.done_files()));
+            summary.insert("bytes".to_string(), json!(self.progress.done_bytes()));
+            summary.insert(
+                "elapsed_secs".to_string(),
+                json!(self.progress.elapsed().as_secs_f64()),
+            );
+            for (status, count) in &self.counts {
+                summary.insert(status.to_string(), json!(count));
+            }
+            if self.drop_when_full {
+                summary.insert("dropped_events".to_string(), json!(self.dropped));
+            }
+            if let Some((selected, total)) = self.partial {
+                summary.insert("partial".to_string(), json!(true));
+                summary.insert("selected_files".to_string(), json!(selected));
+                summary.insert("manifest_files".to_string(), json!(total));
+            }
+            for (group, group_summary) in self.sorted_groups() {
+                let mut line = serde_json::Map::new();
+                line.insert("group".to_string(), json!(group));
+                for (status, count) in &group_summary.counts {
+                    line.insert(status.to_string(), json!(count));
+                }
+                line.insert("bytes".to_string(), json!(group_summary.bytes));
+                self.print_json(Value::Object(line));
+            }
+            if let Some(content_types) = &self.content_types {
+                let breakdown: serde_json::Map<_, _> = content_types
+                    .iter()
+                    .map(|(name, (files, bytes))| {
+                        (name.to_string(), json!({ "files": files, "bytes": bytes }))
+                    })
+                    .collect();
+                self.print_json(json!({ "content_types": breakdown }));
+            }
+            self.print_json(json!({ "summary": Value::Object(summary) }));
+            self.close_output();
+            if self.dropped > 0 {
+                log_stderr!("警告: 输出队列已满, 丢弃了 {} 条事件", self.dropped);
+            }
+        } else {
+            for anomaly in &anomalies {
+                log_stderr!("警告: {}", anomaly);
+            }
+            for (group, summary) in self.sorted_groups() {
+                println!(
+                    "[{} | 成功: {} | 失败: {} | 缺失: {} | 易变: {} | {} 字节]",
+                    group,
+                    summary.count("passed"),
+                    summary.count("failed"),
+                    summary.count("missing"),
+                    summary.count("volatile"),
+                    summary.bytes
+                );
+            }
+            // 文件数多的类型在前
+            if let Some(content_types) = &self.content_types {
+                let mut breakdown: Vec<_> = content_types.iter().collect();
+                breakdown.sort_by_key(|(_, (files, _))| std::cmp::Reverse(*files));
+                for (name, (files, bytes)) in breakdown {
+                    println!("[内容类型 | {} | {} 个文件 | {} 字节]", name, files, bytes);
                 }
             }
-            drop(permit);
+            if let Some((selected, total)) = self.partial {
+                println!(
+                    "[部分校验 | 只校验了清单中 {} 个文件中的 {} 个]",
+                    total, selected
+                );
+            }
+        }
+    }
+
+    // 有问题的目录排在前面
+    fn sorted_groups(&self) -> Vec<(&String, &GroupSummary)> {
+        let mut groups: Vec<_> = self.groups.iter().collect();
+        groups.sort_by(|(a, a_summary), (b, b_summary)| {
+            b_summary
+                .has_problems()
+                .cmp(&a_summary.has_problems())
+                .then_with(|| self.sort_order.compare_str(a, b))
         });
-        handles.push(handle);
+        groups
+    }
+
+    // 每一行JSON都带上运行ID, 队列满时等待输出线程写出
+    fn print_json(&self, line: Value) {
+        let line = self.tag_json(line);
+        match self
+            .output
+            .as_ref()
+            .and_then(|output| output.sender.as_ref())
+        {
+            Some(sender) => {
+                let _ = sender.send(line);
+            }
+            None => println!("{}", line),
+        }
+    }
+
+    // 逐个文件的事件, 设置了丢弃策略时队列满了就丢弃并计数
+    fn stream_json(&mut self, line: Value) {
+        if !self.drop_when_full {
+            self.print_json(line);
+            return;
+        }
+        let line = self.tag_json(line);
+        match self
+            .output
+            .as_ref()
+            .and_then(|output| output.sender.as_ref())
+        {
+            Some(sender) => {
+                if let Err(TrySendError::Full(_)) = sender.try_send(line) {
+                    self.dropped += 1;
+                }
+            }
+            None => println!("{}", line),
+        }
+    }
+
+    fn tag_json(&self, mut line: Value) -> String {
+        if let Value::Object(fields) = &mut line {
+            fields.insert("run_id".to_string(), json!(self.run_id));
+        }
+        line.to_string()
+    }
+
+    // 程序退出前必须调用, 否则队列中的行可能没有写出; 之后的输出直接写到标准输出
+    fn close_output(&mut self) {
+        self.output = None;
+    }
+
+    fn lint_issue(&mut self, issue: &LintIssue) {
+        if self.json {
+            self.print_json(json!({
+                "line": issue.line,
+                "problem": issue.problem.name(),
+                "message": issue.problem.to_string(),
+                "fixable": issue.problem.is_fixable(),
+            }));
+        } else {
+            let location = match issue.line {
+                Some(line) => format!("第{}行", line),
+                None => "清单".to_string(),
+            };
+            if issue.problem.is_fixable() {
+                println!("[{} | {}]", location, issue.problem);
+            } else {
+                println!("[{} | {} | 无法自动修复]", location, issue.problem);
+            }
+        }
+    }
+
+    fn fixed(&mut self, file_path: &Path, issues: usize) {
+        if self.json {
+            self.print_json(json!({
+                "path": file_path.to_string_lossy(),
+                "status": "fixed",
+                "issues": issues,
+            }));
+        } else {
+            println!("[{} | 已修复 {} 个问题]", file_path.display(), issues);
+        }
+    }
+
+    // 文本输出开头先输出运行ID, JSON输出的每一行都带有运行ID
+    fn started(&mut self) {
+        if !self.json {
+            println!("[运行 | {}]", self.run_id);
+        }
+    }
+
+    fn resumed(&mut self, journal_path: &Path, entries: usize) {
+        if entries == 0 {
+            return;
+        }
+        if self.json {
+            self.print_json(json!({
+                "path": journal_path.to_string_lossy(),
+                "status": "resumed",
+                "entries": entries,
+            }));
+        } else {
+            println!(
+                "[{} | 从上次中断的运行中恢复了 {} 个哈希]",
+                journal_path.display(),
+                entries
+            );
+        }
+    }
+
+    fn manifest_summary(&mut self, sub_folder: &str, url: &str, report: &VerifyReport) {
+        self.clear_progress();
+        let statuses = [
+            VerifyStatus::Passed,
+            VerifyStatus::Failed,
+            VerifyStatus::Missing,
+            VerifyStatus::Volatile,
+        ];
+        if self.json {
+            let mut line = serde_json::Map::new();
+            line.insert("manifest".to_string(), json!(url));
+            line.insert("folder".to_string(), json!(sub_folder));
+            for status in statuses {
+                line.insert(status_name(status).to_string(), json!(report.count(status)));
+            }
+            line.insert("ok".to_string(), json!(report.is_ok()));
+            self.print_json(Value::Object(line));
+        } else {
+            let counts: Vec<_> = statuses
+                .iter()
+                .map(|status| format!("{}: {}", status_text(*status), report.count(*status)))
+                .collect();
+            println!("[{} | {} | {}]", sub_folder, url, counts.join(" | "));
+        }
+    }
+
+    // 部分校验要在结束时标明只校验了一部分, 遇到失败时不能提前退出
+    fn set_partial(&mut self, selected: usize, total: usize) {
+        self.partial = Some((selected, total));
+        self.stop_on_failure = false;
+    }
+
+    fn pruned(&mut self, file_path: &Path) {
+        self.clear_progress();
+        if self.json {
+            self.print_json(json!({ "path": file_path.to_string_lossy(), "status": "pruned" }));
+        } else {
+            println!("[{} | 已清理]", file_path.display());
+        }
+    }
+
+    fn sorted_paths<'a>(&self, file_paths: &'a [PathBuf]) -> Vec<&'a PathBuf> {
+        let mut file_paths: Vec<_> = file_paths.iter().collect();
+        file_paths.sort_by(|a, b| self.sort_order.compare(a, b));
+        file_paths
+    }
+
+    fn duplicates(&self, sets: &[DuplicateSet]) {
+        let reclaimable: u64 = sets.iter().map(|set| set.reclaimable()).sum();
+        if self.json {
+            for set in sets {
+                let paths: Vec<_> = self
+                    .sorted_paths(&set.file_paths)
+                    .iter()
+                    .map(|file_path| file_path.to_string_lossy())
+                    .collect();
+                self.print_json(json!({
+                    "hash": set.hash.to_string(),
+                    "size": set.size,
+                    "paths": paths,
+                    "status": "duplicate",
+                }));
+            }
+            self.print_json(
+                json!({ "duplicates": { "sets": sets.len(), "reclaimable_bytes": reclaimable } }),
+            );
+        } else {
+            for set in sets {
+                println!("[重复 | {} | {} 字节]", set.hash, set.size);
+                for file_path in self.sorted_paths(&set.file_paths) {
+                    println!("    {}", file_path.display());
+                }
+            }
+            println!("共 {} 组重复文件, 可回收 {} 字节", sets.len(), reclaimable);
+        }
+    }
+}
+
+// 文件所在的顶层目录, 直接位于根目录下的文件归入 "."
+fn top_level_dir(root: &Path, file_path: &Path) -> String {
+    let relative_path = file_path.strip_prefix(root).unwrap_or(file_path);
+    let mut components = relative_path.components();
+    match (components.next(), components.next()) {
+        (Some(first), Some(_)) => first.as_os_str().to_string_lossy().into_owned(),
+        _ => ".".to_string(),
+    }
+}
+
+fn status_text(status: VerifyStatus) -> &'static str {
+    match status {
+        VerifyStatus::Passed => "成功",
+        VerifyStatus::Failed => "失败",
+        VerifyStatus::Missing => "缺失",
+        VerifyStatus::Volatile => "易变",
+        _ => "未知",
+    }
+}
+
+fn event_status(event: &Event) -> Option<&'static str> {
+    match event {
+        Event::Hashed { .. } => Some("hashed"),
+        Event::Added(_) => Some("added"),
+        Event::Changed(_) => Some("changed"),
+        Event::Removed(_) => Some("removed"),
+        Event::Skipped { .. } => Some("skipped"),
+        Event::Verified { status, .. } => Some(status_name(*status)),
+        _ => None,
+    }
+}
+
+fn status_name(status: VerifyStatus) -> &'static str {
+    match status {
+        VerifyStatus::Passed => "passed",
+        VerifyStatus::Failed => "failed",
+        VerifyStatus::Missing => "missing",
+        VerifyStatus::Volatile => "volatile",
+        _ => "unknown",
+    }
+}
+
+fn event_json(event: &Event) -> Option<Value> {
+    let status = event_status(event)?;
+    Some(match event {
+        Event::Hashed {
+            file_path, hash, ..
+        } => json!({
+            "path": file_path.to_string_lossy(),
+            "hash": hash.to_string(),
+            "status": status,
+        }),
+        Event::Added(file_path) | Event::Changed(file_path) | Event::Removed(file_path) => json!({
+            "path": file_path.to_string_lossy(),
+            "status": status,
+        }),
+        Event::Skipped { file_path, reason } => json!({
+            "path": file_path.to_string_lossy(),
+            "status": status,
+            "reason": reason.name(),
+        }),
+        Event::Verified {
+            file_path, hash, ..
+        } => json!({
+            "path": file_path.to_string_lossy(),
+            "hash": hash.map(|hash| hash.to_string()),
+            "status": status,
+        }),
+        _ => return None,
+    })
+}
+
+fn print_event(event: Event, stop_on_failure: bool) {
+    match event {
+        Event::Hashed {
+            file_path, hash, ..
+        } => println!("[{} | {}]", file_path.display(), hash),
+        Event::Added(file_path) => println!("[{} | 新增]", file_path.display()),
+        Event::Changed(file_path) => println!("[{} | 更新]", file_path.display()),
+        Event::Removed(file_path) => println!("[{} | 删除]", file_path.display()),
+        Event::Warning(err) => log_stderr!("警告: {}", err),
+        Event::Skipped { file_path, reason } => {
+            let reason = match reason {
+                SkipReason::Hidden => "隐藏文件",
+                SkipReason::Excluded => "被排除",
+                SkipReason::NotIncluded => "未被包含",
+                SkipReason::Symlink => "符号链接",
+                SkipReason::SpecialFile => "特殊文件",
+                _ => "其他原因",
+            };
+            println!("[{} | 跳过: {}]", file_path.display(), reason)
+        }
+        Event::Verified {
+            file_path, status, ..
+        } => {
+            println!("[{} | {}]", file_path.display(), status_text(status));
+            if stop_on_failure && matches!(status, VerifyStatus::Failed | VerifyStatus::Missing) {
+                exit(1);
+            }
+        }
+        _ => {}
     }
-    (rx, handles)
 }
@@ -0,0 +1,881 @@
+use crate::format::{
+    encode_gnu_path, format_line, is_absolute_entry, normalize_path, parse_line, rclone_algorithm,
+    read_lines, ManifestLine, MANIFEST_VERSION,
+};
+use crate::{
+    create_hasher, get_file_meta, journal_path, Digest, Error, GlobPattern, ManifestFormat,
+    OutsideRoot, Result, SortOrder, VerifyStatus, DEFAULT_ALGORITHM, DEFAULT_BUFFER_SIZE,
+};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use xxhash_rust::xxh3::{xxh3_64, xxh3_64_with_seed};
+
+/// 文件的大小和修改时间, 更新时用来判断文件是否改变
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FileMeta {
+    pub size: u64,
+    /// Unix纪元以来的整秒数和不足一秒的纳秒数, 纪元之前的时间记为0
+    pub mtime: u64,
+    pub mtime_nanos: u32,
+}
+
+impl FileMeta {
+    pub fn new(size: u64, mtime: u64, mtime_nanos: u32) -> FileMeta {
+        FileMeta {
+            size,
+            mtime,
+            mtime_nanos,
+        }
+    }
+}
+
+/// 与清单一起写出的其他工具的校验文件格式
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CompanionFormat {
+    Sha256sum,
+    Sfv,
+}
+
+impl CompanionFormat {
+    pub fn from_name(name: &str) -> Result<CompanionFormat> {
+        match name {
+            "sha256sum" => Ok(CompanionFormat::Sha256sum),
+            "sfv" => Ok(CompanionFormat::Sfv),
+            _ => Err(Error::UnsupportedCompanionFormat(name.to_string())),
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            CompanionFormat::Sha256sum => "sha256",
+            CompanionFormat::Sfv => "sfv",
+        }
+    }
+}
+
+/// 附加格式需要的哈希, 没有要求的格式为 None
+#[derive(Clone, Copy, Default)]
+#[non_exhaustive]
+pub struct CompanionHashes {
+    pub sha256: Option<[u8; 32]>,
+    pub crc32: Option<u32>,
+}
+
+/// 清单中一个文件的条目
+#[derive(Clone)]
+#[non_exhaustive]
+pub struct HashRecord {
+    pub hash: Digest,
+    pub meta: Option<FileMeta>,
+    pub companions: CompanionHashes,
+}
+
+impl HashRecord {
+    /// 没有附加哈希的记录, 供库外的 Source 实现和测试构造清单条目
+    pub fn new(hash: Digest, meta: Option<FileMeta>) -> HashRecord {
+        HashRecord {
+            hash,
+            meta,
+            companions: CompanionHashes::default(),
+        }
+    }
+}
+
+/// 文件夹中各文件的哈希, 条目按完整路径记录, 写入时转换为相对于文件夹的路径
+#[derive(Clone)]
+pub struct Manifest {
+    folder_path: PathBuf,
+    algorithm: String,
+    format: ManifestFormat,
+    timestamps: bool,
+    run_id: Option<String>,
+    sort_order: SortOrder,
+    outside_root: OutsideRoot,
+    absolute_entries: Vec<PathBuf>,
+    file_paths: Vec<PathBuf>,
+    records: HashMap<PathBuf, HashRecord>,
+}
+
+impl Manifest {
+    pub fn new(folder_path: &Path, algorithm: &str) -> Manifest {
+        Manifest {
+            folder_path: folder_path.to_path_buf(),
+            algorithm: algorithm.to_string(),
+            format: ManifestFormat::Native,
+            timestamps: true,
+            run_id: None,
+            sort_order: SortOrder::default(),
+            outside_root: OutsideRoot::default(),
+            absolute_entries: Vec::new(),
+            file_paths: Vec::new(),
+            records: HashMap::new(),
+        }
+    }
+
+    /// 读取哈希文件, 条目按 folder_path 解析
+    pub fn read(folder_path: &Path, hash_file_path: &Path) -> Result<Manifest> {
+        let file = File::open(hash_file_path).map_err(|err| Error::io(hash_file_path, err))?;
+        Manifest::from_reader(folder_path, BufReader::new(file))
+    }
+
+    /// 从任意来源解析清单, 例如从网络下载的清单
+    pub fn from_reader(folder_path: &Path, reader: impl BufRead) -> Result<Manifest> {
+        let mut manifest = Manifest::new(folder_path, DEFAULT_ALGORITHM);
+        let mut header_algorithm = None;
+        let mut entry_algorithm = None;
+        let mut entries = Vec::new();
+        let mut version = 1;
+
+        for (index, line) in read_lines(reader).enumerate() {
+            let line = line?;
+            match parse_line(&line, version)? {
+                Some(ManifestLine::Header { key, value }) => match key {
+                    "algorithm" => header_algorithm = Some(value.to_string()),
+                    "timestamps" => manifest.timestamps = value != "no",
+                    "run" => manifest.run_id = Some(value.to_string()),
+                    "version" => {
+                        version = match value.parse() {
+                            Ok(value) if (1..=MANIFEST_VERSION).contains(&value) => value,
+                            _ => return Err(Error::UnsupportedVersion(value.to_string())),
+                        }
+                    }
+                    _ => {}
+                },
+                Some(ManifestLine::Entry {
+                    format,
+                    algorithm,
+                    path,
+                    hash,
+                    meta,
+                }) => {
+                    if entries.is_empty() {
+                        manifest.format = format;
+                        entry_algorithm = algorithm;
+                    }
+                    // 绝对路径的条目不拼接到文件夹上
+                    let absolute = is_absolute_entry(&path);
+                    let key = if path.is_absolute() {
+                        path
+                    } else {
+                        folder_path.join(&path)
+                    };
+                    if absolute {
+                        manifest.absolute_entries.push(key.clone());
+                    }
+                    entries.push((key, hash.to_string(), meta));
+                }
+                // 空行和注释之外无法识别的行说明清单已损坏, 不能当作没有这一行
+                None if line.trim().is_empty() || line.starts_with('#') => {}
+                None => return Err(Error::UnrecognizedLine { line: index + 1 }),
+            }
+        }
+
+        // 没有算法头的清单根据条目推断算法, 旧版清单使用默认算法
+        if let Some(algorithm) = header_algorithm.as_deref().or(entry_algorithm) {
+            manifest.algorithm = algorithm.to_string();
+        }
+
+        let digest_len = create_hasher(&manifest.algorithm)?.digest_len();
+        for (key, hash, meta) in entries {
+            let record = HashRecord {
+                hash: Digest::from_hex(&hash, digest_len)?,
+                meta,
+                companions: CompanionHashes::default(),
+            };
+            manifest.insert(key, record);
+        }
+        Ok(manifest)
+    }
+
+    /// 导入 rclone hashsum 的输出, 用于校验之前从云端记录的哈希
+    /// 每行为"哈希  相对路径", 路径不转义, 也不记录算法, 需要由调用方指定哈希类型
+    pub fn from_rclone(
+        folder_path: &Path,
+        hash_type: &str,
+        reader: impl BufRead,
+    ) -> Result<Manifest> {
+        let algorithm = rclone_algorithm(hash_type);
+        let digest_len = create_hasher(algorithm)?.digest_len();
+        let mut manifest = Manifest::new(folder_path, algorithm);
+
+        for line in read_lines(reader) {
+            let line = line?;
+            let Some((hash, path)) = line.split_once("  ") else {
+                continue;
+            };
+            // 没有大小和修改时间, 与 rclone --checksum 一样只按哈希判断
+            let record = HashRecord {
+                hash: Digest::from_hex(hash.trim(), digest_len)?,
+                meta: None,
+                companions: CompanionHashes::default(),
+            };
+            manifest.insert(folder_path.join(path), record);
+        }
+        Ok(manifest)
+    }
+
+    /// 先写入临时文件再替换, 中断时不会留下不完整的清单
+    pub fn write(&self, hash_file_path: &Path) -> Result<()> {
+        // 先写入同目录的临时文件再重命名, 避免写入中断时损坏原有的清单
+        let temp_file = TempFile::new(hash_file_path);
+        self.write_lines(temp_file.path())?;
+        temp_file.persist()
+    }
+
+    fn write_lines(&self, hash_file_path: &Path) -> Result<()> {
+        let mut file = BufWriter::new(create_file(hash_file_path)?);
+
+        // xxhsum和BSD格式通过哈希本身表示算法, 不写算法头以保持兼容
+        if self.format == ManifestFormat::Native {
+            writeln!(
+                file,
+                "# version: {}\n# algorithm: {}",
+                MANIFEST_VERSION, self.algorithm
+            )
+            .map_err(|err| Error::io(hash_file_path, err))?;
+            if !self.timestamps {
+                writeln!(file, "# timestamps: no").map_err(|err| Error::io(hash_file_path, err))?;
+            }
+            // 运行ID每次都不同, 不记录时间戳时也不写入, 保证清单可以重复生成
+            if let Some(run_id) = self.run_id.as_ref().filter(|_| self.timestamps) {
+                writeln!(file, "# run: {}", run_id)
+                    .map_err(|err| Error::io(hash_file_path, err))?;
+            }
+        }
+
+        // 按相对路径排序, 使相同的内容在不同平台和遍历顺序下生成相同的清单
+        let mut entries = Vec::new();
+        for (file_path, record) in self.iter() {
+            if let Some(entry_path) = self.entry_path(file_path)? {
+                entries.push((entry_path, record));
+            }
+        }
+        entries.sort_by(|a, b| self.sort_order.compare(&a.0, &b.0));
+
+        for (entry_path, record) in entries {
+            let meta = if self.timestamps { record.meta } else { None };
+            let line = format_line(
+                self.format,
+                &self.algorithm,
+                &entry_path,
+                &record.hash.to_string(),
+                meta,
+            )?;
+            writeln!(file, "{}", line).map_err(|err| Error::io(hash_file_path, err))?;
+        }
+        file.into_inner()
+            .map_err(|err| Error::io(hash_file_path, err.into_error()))?
+            .sync_all()
+            .map_err(|err| Error::io(hash_file_path, err))
+    }
+
+    pub fn write_companion(
+        &self,
+        companion_file_path: &Path,
+        companion_format: CompanionFormat,
+    ) -> Result<()> {
+        let temp_file = TempFile::new(companion_file_path);
+        let mut file = BufWriter::new(create_file(temp_file.path())?);
+
+        for (file_path, record) in self.iter() {
+            let Some(entry_path) = self.entry_path(file_path)? else {
+                continue;
+            };
+            let (relative_path, escaped) = encode_gnu_path(&entry_path);
+            match companion_format {
+                CompanionFormat::Sha256sum => {
+                    // 不在附加哈希策略范围内的文件没有附加哈希, 不写入
+                    let Some(sha256) = record.companions.sha256 else {
+                        continue;
+                    };
+                    let sha256_hex: String = sha256.iter().map(|b| format!("{:02x}", b)).collect();
+                    let prefix = if escaped { "\\" } else { "" };
+                    writeln!(file, "{}{}  {}", prefix, sha256_hex, relative_path)
+                }
+                CompanionFormat::Sfv => {
+                    let Some(crc32) = record.companions.crc32 else {
+                        continue;
+                    };
+                    writeln!(file, "{} {:08X}", relative_path, crc32)
+                }
+            }
+            .map_err(|err| Error::io(companion_file_path, err))?;
+        }
+        file.into_inner()
+            .map_err(|err| Error::io(companion_file_path, err.into_error()))?
+            .sync_all()
+            .map_err(|err| Error::io(companion_file_path, err))?;
+        temp_file.persist()
+    }
+
+    pub fn folder_path(&self) -> &Path {
+        &self.folder_path
+    }
+
+    pub fn algorithm(&self) -> &str {
+        &self.algorithm
+    }
+
+    pub fn format(&self) -> ManifestFormat {
+        self.format
+    }
+
+    pub fn set_format(&mut self, format: ManifestFormat) {
+        self.format = format;
+    }
+
+    pub fn timestamps(&self) -> bool {
+        self.timestamps
+    }
+
+    /// 不写入大小和修改时间, 更新时会重新计算所有文件的哈希
+    pub fn set_timestamps(&mut self, timestamps: bool) {
+        self.timestamps = timestamps;
+    }
+
+    /// 生成或更新清单的那次运行的ID, 用于和日志及报告关联
+    pub fn run_id(&self) -> Option<&str> {
+        self.run_id.as_deref()
+    }
+
+    pub fn set_run_id(&mut self, run_id: &str) {
+        self.run_id = Some(run_id.to_string());
+    }
+
+    pub fn sort_order(&self) -> SortOrder {
+        self.sort_order
+    }
+
+    /// 只影响写入清单时条目的顺序
+    pub fn set_sort_order(&mut self, sort_order: SortOrder) {
+        self.sort_order = sort_order;
+    }
+
+    pub fn outside_root(&self) -> OutsideRoot {
+        self.outside_root
+    }
+
+    pub fn set_outside_root(&mut self, outside_root: OutsideRoot) {
+        self.outside_root = outside_root;
+    }
+
+    /// 读取时以绝对路径记录的条目, 严格模式下可以据此拒绝清单
+    pub fn absolute_entries(&self) -> &[PathBuf] {
+        &self.absolute_entries
+    }
+
+    /// 不在清单文件夹中的条目, 写入前可以据此报告警告
+    pub fn outside_root_paths(&self) -> Vec<&Path> {
+        self.file_paths
+            .iter()
+            .filter(|file_path| !file_path.starts_with(&self.folder_path))
+            .map(PathBuf::as_path)
+            .collect()
+    }
+
+    // 写入清单的路径: 文件夹中的条目用相对路径, 其他条目按策略处理, 返回 None 表示跳过
+    fn entry_path<'a>(&self, file_path: &'a Path) -> Result<Option<Cow<'a, Path>>> {
+        match file_path.strip_prefix(&self.folder_path) {
+            Ok(relative_path) => Ok(Some(Cow::Borrowed(relative_path))),
+            Err(_) => match self.outside_root {
+                OutsideRoot::Absolute => Ok(Some(Cow::Owned(
+                    std::path::absolute(file_path).map_err(|err| Error::io(file_path, err))?,
+                ))),
+                OutsideRoot::Skip => Ok(None),
+                OutsideRoot::Fail => Err(Error::OutsideRoot(file_path.to_path_buf())),
+            },
+        }
+    }
+
+    /// 路径统一保存为NFC形式, 使不同平台上的同名文件对应同一个条目
+    pub fn insert(&mut self, mut file_path: PathBuf, record: HashRecord) {
+        if let Cow::Owned(normalized) = normalize_path(&file_path) {
+            file_path = normalized;
+        }
+        if self.records.insert(file_path.clone(), record).is_none() {
+            self.file_paths.push(file_path);
+        }
+    }
+
+    pub fn get(&self, file_path: &Path) -> Option<&HashRecord> {
+        self.records.get(normalize_path(file_path).as_ref())
+    }
+
+    /// 用已经在内存中的内容校验一个条目, 例如刚下载还没有写入磁盘的文件
+    /// 相对路径按清单所在的文件夹解析
+    pub fn verify_bytes(&self, file_path: &Path, mut reader: impl Read) -> Result<VerifyStatus> {
+        let file_path = self.folder_path.join(file_path);
+        let record = self
+            .get(&file_path)
+            .ok_or_else(|| Error::MissingHash(file_path.clone()))?;
+        let mut hasher = create_hasher(&self.algorithm)?;
+        let mut buffer = vec![0; DEFAULT_BUFFER_SIZE];
+        loop {
+            let n = match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(Error::io(&file_path, err)),
+            };
+            hasher.update(&buffer[..n]);
+        }
+        if hasher.finish() == record.hash {
+            Ok(VerifyStatus::Passed)
+        } else {
+            Ok(VerifyStatus::Failed)
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&PathBuf, &HashRecord)> {
+        self.file_paths
+            .iter()
+            .map(|file_path| (file_path, &self.records[file_path]))
+    }
+
+    pub fn len(&self) -> usize {
+        self.file_paths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.file_paths.is_empty()
+    }
+
+    /// 按相对路径比较两个清单, 只比较已记录的哈希, 不重新计算
+    pub fn diff(&self, other: &Manifest) -> Result<ManifestDiff> {
+        if self.algorithm != other.algorithm {
+            return Err(Error::AlgorithmMismatch {
+                expected: self.algorithm.clone(),
+                found: other.algorithm.clone(),
+            });
+        }
+
+        let records: HashMap<&Path, &HashRecord> = self
+            .iter()
+            .map(|(file_path, record)| (self.relative_path(file_path), record))
+            .collect();
+        let mut other_paths = HashSet::new();
+        let mut diff = ManifestDiff::default();
+        for (file_path, record) in other.iter() {
+            let relative_path = other.relative_path(file_path);
+            other_paths.insert(relative_path);
+            match records.get(relative_path) {
+                Some(old_record) if old_record.hash == record.hash => {}
+                Some(_) => diff.modified.push(file_path.clone()),
+                None => diff.added.push(file_path.clone()),
+            }
+        }
+        for (file_path, _) in self.iter() {
+            if !other_paths.contains(self.relative_path(file_path)) {
+                diff.removed.push(file_path.clone());
+            }
+        }
+        Ok(diff)
+    }
+
+    /// 按哈希分组, 可回收空间多的组在前, 空文件不计入
+    pub fn duplicates(&self) -> Vec<DuplicateSet> {
+        let mut set_indexes: HashMap<&Digest, usize> = HashMap::new();
+        let mut sets: Vec<DuplicateSet> = Vec::new();
+        for (file_path, record) in self.iter() {
+            let size = record.meta.map_or(0, |meta| meta.size);
+            if record.meta.is_some() && size == 0 {
+                continue;
+            }
+            match set_indexes.get(&record.hash) {
+                Some(&index) => sets[index].file_paths.push(file_path.clone()),
+                None => {
+                    set_indexes.insert(&record.hash, sets.len());
+                    sets.push(DuplicateSet {
+                        hash: record.hash.clone(),
+                        size,
+                        file_paths: vec![file_path.clone()],
+                    });
+                }
+            }
+        }
+        sets.retain(|set| set.file_paths.len() > 1);
+        sets.sort_by_key(|set| std::cmp::Reverse(set.reclaimable()));
+        sets
+    }
+
+    /// 按相对路径的哈希抽样, 相同的种子在任何机器上都选出相同的文件
+    pub fn sample(&self, seed: u64, fraction: f64) -> Result<Manifest> {
+        if !(0.0..=1.0).contains(&fraction) {
+            return Err(Error::InvalidFraction(fraction));
+        }
+        Ok(self.filter(|relative_path| {
+            let hash = xxh3_64_with_seed(relative_path.as_bytes(), seed);
+            ((hash >> 11) as f64 / (1u64 << 53) as f64) < fraction
+        }))
+    }
+
+    /// 把清单分成 count 片并返回第 index 片(从0开始), 所有分片合起来正好是整个清单
+    pub fn slice(&self, index: usize, count: usize) -> Result<Manifest> {
+        if index >= count {
+            return Err(Error::InvalidSlice { index, count });
+        }
+        Ok(self.filter(|relative_path| {
+            xxh3_64(relative_path.as_bytes()) % count as u64 == index as u64
+        }))
+    }
+
+    /// 子集中所有条目的合并摘要, 两个清单对应子集的摘要相同说明其中的路径和哈希都相同
+    /// 条目按编码后的相对路径排序后依次计入, 与清单的格式, 排序方式和根目录无关
+    pub fn subset_digest(&self, filter: &SubsetFilter) -> Result<Digest> {
+        let mut entries: Vec<(String, &Digest)> = self
+            .iter()
+            .map(|(file_path, record)| (self.relative_path(file_path), record))
+            .filter(|(relative_path, _)| filter.matches(relative_path))
+            .map(|(relative_path, record)| (encode_gnu_path(relative_path).0, &record.hash))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut hasher = create_hasher(&self.algorithm)?;
+        for (relative_path, hash) in entries {
+            hasher.update(relative_path.as_bytes());
+            hasher.update(b"\0");
+            hasher.update(hash.as_bytes());
+        }
+        Ok(hasher.finish())
+    }
+
+    /// 按清单中的顺序取出条目, 文件数或总字节数再加一个就超过上限时停止
+    /// 没有记录大小的条目按磁盘上的当前大小计算, 文件不存在时按0计算
+    pub fn limit(&self, max_files: Option<usize>, max_bytes: Option<u64>) -> Manifest {
+        let mut manifest = Manifest::new(&self.folder_path, &self.algorithm);
+        manifest.format = self.format;
+        manifest.timestamps = self.timestamps;
+        let mut bytes = 0u64;
+        for (file_path, record) in self.iter() {
+            if max_files.is_some_and(|max_files| manifest.len() >= max_files) {
+                break;
+            }
+            let size = match record.meta {
+                Some(meta) => meta.size,
+                None => get_file_meta(file_path).map_or(0, |meta| meta.size),
+            };
+            if max_bytes.is_some_and(|max_bytes| bytes.saturating_add(size) > max_bytes) {
+                break;
+            }
+            bytes += size;
+            manifest.insert(file_path.clone(), record.clone());
+        }
+        manifest
+    }
+
+    // 用统一编码后的相对路径判断, 使不同平台和根目录下的结果一致
+    fn filter(&self, mut keep: impl FnMut(&str) -> bool) -> Manifest {
+        let mut manifest = Manifest::new(&self.folder_path, &self.algorithm);
+        manifest.format = self.format;
+        manifest.timestamps = self.timestamps;
+        for (file_path, record) in self.iter() {
+            let (relative_path, _) = encode_gnu_path(self.relative_path(file_path));
+            if keep(&relative_path) {
+                manifest.insert(file_path.clone(), record.clone());
+            }
+        }
+        manifest
+    }
+
+    pub(crate) fn relative_path<'a>(&self, file_path: &'a Path) -> &'a Path {
+        file_path
+            .strip_prefix(&self.folder_path)
+            .unwrap_or(file_path)
+    }
+}
+
+/// 按相对于清单文件夹的路径选取条目
+#[non_exhaustive]
+pub enum SubsetFilter {
+    All,
+    /// 按路径组件匹配, photos 包含 photos/a.jpg, 不包含 photos2/a.jpg
+    Prefix(PathBuf),
+    /// 与 --include 相同的匹配规则
+    Glob(GlobPattern),
+}
+
+impl SubsetFilter {
+    pub fn prefix(prefix: impl AsRef<Path>) -> SubsetFilter {
+        SubsetFilter::Prefix(normalize_path(prefix.as_ref()).into_owned())
+    }
+
+    pub fn glob(pattern: &str) -> Result<SubsetFilter> {
+        Ok(SubsetFilter::Glob(GlobPattern::new(pattern)?))
+    }
+
+    pub fn matches(&self, relative_path: &Path) -> bool {
+        match self {
+            SubsetFilter::All => true,
+            SubsetFilter::Prefix(prefix) => relative_path.starts_with(prefix),
+            SubsetFilter::Glob(pattern) => pattern.matches(relative_path),
+        }
+    }
+}
+
+/// 两个清单的差异, 路径相对于清单文件夹
+#[derive(Default)]
+#[non_exhaustive]
+pub struct ManifestDiff {
+    pub added: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+    pub modified: Vec<PathBuf>,
+}
+
+impl ManifestDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+
+    pub fn sort(&mut self, sort_order: SortOrder) {
+        for file_paths in [&mut self.added, &mut self.removed, &mut self.modified] {
+            file_paths.sort_by(|a, b| sort_order.compare(a, b));
+        }
+    }
+}
+
+/// 内容相同的一组文件
+#[non_exhaustive]
+pub struct DuplicateSet {
+    pub hash: Digest,
+    pub size: u64,
+    pub file_paths: Vec<PathBuf>,
+}
+
+impl DuplicateSet {
+    /// 只保留一份时可以释放的空间
+    pub fn reclaimable(&self) -> u64 {
+        self.size * (self.file_paths.len() as u64 - 1)
+    }
+}
+
+pub(crate) fn temp_file_path(file_path: &Path) -> PathBuf {
+    let mut file_name = OsString::from(".");
+    file_name.push(file_path.file_name().unwrap_or_default());
+    file_name.push(".tmp");
+    file_path.with_file_name(file_name)
+}
+
+// 写入目标文件前使用的临时文件, 重命名为目标文件之前出错或 panic 时在丢弃时删除
+struct TempFile {
+    temp_file_path: PathBuf,
+    file_path: PathBuf,
+    persisted: bool,
+}
+
+impl TempFile {
+    fn new(file_path: &Path) -> TempFile {
+        TempFile {
+            temp_file_path: temp_file_path(file_path),
+            file_path: file_path.to_path_buf(),
+            persisted: false,
+        }
+    }
+
+    fn path(&self) -> &Path {
+        &self.temp_file_path
+    }
+
+    fn persist(mut self) -> Result<()> {
+        fs::rename(&self.temp_file_path, &self.file_path)
+            .map_err(|err| Error::io(&self.file_path, err))?;
+        self.persisted = true;
+        sync_parent_dir(&self.file_path)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = fs::remove_file(&self.temp_file_path);
+        }
+    }
+}
+
+/// 之前崩溃或被终止的运行留在清单旁的临时文件和预写日志
+/// 锁文件在每次运行时复用, 不属于残留文件
+pub fn stale_artifacts(hash_file_path: &Path) -> Vec<PathBuf> {
+    let mut candidates = vec![temp_file_path(hash_file_path), journal_path(hash_file_path)];
+    for companion_format in [CompanionFormat::Sha256sum, CompanionFormat::Sfv] {
+        candidates.push(temp_file_path(
+            &hash_file_path.with_extension(companion_format.extension()),
+        ));
+    }
+    candidates.retain(|file_path| file_path.is_file());
+    candidates
+}
+
+fn create_file(file_path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(file_path)
+        .map_err(|err| Error::io(file_path, err))
+}
+
+// 同步清单所在的目录, 保证删除预写日志之前重命名已经写入磁盘
+#[cfg(unix)]
+fn sync_parent_dir(file_path: &Path) -> Result<()> {
+    let dir_path = match file_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(dir_path)
+        .and_then(|dir| dir.sync_all())
+        .map_err(|err| Error::io(dir_path, err))
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_file_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TestTree, GOLDEN_MANIFEST};
+
+    // 把清单写到单独的目录中并读回原始字节
+    fn manifest_bytes(manifest: &Manifest) -> Vec<u8> {
+        let output = TestTree::new();
+        let hash_file_path = output.join("hash.xxh");
+        manifest.write(&hash_file_path).unwrap();
+        fs::read(&hash_file_path).unwrap()
+    }
+
+    async fn generate_bytes(tree: &TestTree, configure: impl Fn(&mut Manifest)) -> Vec<u8> {
+        let mut manifest = tree.generate().await;
+        configure(&mut manifest);
+        manifest_bytes(&manifest)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn repeated_generate_is_byte_identical() {
+        let tree = TestTree::golden();
+        let no_timestamps = |manifest: &mut Manifest| {
+            manifest.set_timestamps(false);
+            manifest.set_run_id("run");
+        };
+        let first = generate_bytes(&tree, no_timestamps).await;
+        let second = generate_bytes(&tree, no_timestamps).await;
+        assert_eq!(first, second);
+        assert_eq!(String::from_utf8(first).unwrap(), GOLDEN_MANIFEST);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn entries_are_sorted_regardless_of_creation_order() {
+        let names = ["b", "a/2", "a/10", "c.txt", "a/1"];
+        let forward = TestTree::new();
+        for name in names {
+            forward.file(name, name);
+        }
+        let backward = TestTree::new();
+        for name in names.iter().rev() {
+            backward.file(name, name);
+        }
+        let no_timestamps = |manifest: &mut Manifest| manifest.set_timestamps(false);
+        assert_eq!(
+            generate_bytes(&forward, no_timestamps).await,
+            generate_bytes(&backward, no_timestamps).await
+        );
+
+        let natural = generate_bytes(&forward, |manifest| {
+            manifest.set_timestamps(false);
+            manifest.set_sort_order(SortOrder::Natural);
+        })
+        .await;
+        let paths: Vec<String> = String::from_utf8(natural)
+            .unwrap()
+            .lines()
+            .filter_map(|line| line.strip_prefix('['))
+            .map(|line| line.split(" | ").next().unwrap().to_string())
+            .collect();
+        assert_eq!(paths, ["a/1", "a/2", "a/10", "b", "c.txt"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn paths_are_written_in_nfc_with_forward_slashes() {
+        let decomposed = TestTree::new();
+        decomposed.file("dir/cafe\u{301}.txt", "x");
+        let composed = TestTree::new();
+        composed.file("dir/caf\u{e9}.txt", "x");
+
+        let no_timestamps = |manifest: &mut Manifest| manifest.set_timestamps(false);
+        let bytes = generate_bytes(&decomposed, no_timestamps).await;
+        assert_eq!(bytes, generate_bytes(&composed, no_timestamps).await);
+        let text = String::from_utf8(bytes).unwrap();
+        assert!(text.contains("[dir/caf\u{e9}.txt | "), "{}", text);
+        assert!(!text.contains('\\'), "{}", text);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn no_timestamps_ignores_mtime_and_run_id() {
+        let tree = TestTree::golden();
+        let first = generate_bytes(&tree, |manifest| {
+            manifest.set_timestamps(false);
+            manifest.set_run_id("first");
+        })
+        .await;
+        // 改写内容相同的文件, 只改变修改时间
+        tree.file("hello.txt", b"hello world\n");
+        let second = generate_bytes(&tree, |manifest| {
+            manifest.set_timestamps(false);
+            manifest.set_run_id("second");
+        })
+        .await;
+        assert_eq!(first, second);
+
+        let with_timestamps = generate_bytes(&tree, |manifest| manifest.set_run_id("run")).await;
+        let text = String::from_utf8(with_timestamps).unwrap();
+        assert!(text.contains("# run: run"), "{}", text);
+        assert!(text.contains("[hello.txt | eefac9d87100cd1336b2e733a5484425 | 12 | "));
+    }
+
+    #[test]
+    fn rclone_output_uses_the_named_algorithm() {
+        let folder_path = Path::new("root");
+        let md5 = Manifest::from_rclone(
+            folder_path,
+            "md5",
+            &b"b1946ac92492d2347c6235b4d2611184  dir/a b\n"[..],
+        )
+        .unwrap();
+        assert_eq!(md5.algorithm(), "md5");
+        let record = md5.get(&folder_path.join("dir/a b")).unwrap();
+        assert_eq!(record.hash.to_string(), "b1946ac92492d2347c6235b4d2611184");
+        assert_eq!(
+            md5.verify_bytes(Path::new("dir/a b"), &b"hello\n"[..])
+                .unwrap(),
+            VerifyStatus::Passed
+        );
+
+        let crc32 = Manifest::from_rclone(folder_path, "crc32", &b"363a3020  a\n"[..]).unwrap();
+        assert_eq!(
+            crc32.verify_bytes(Path::new("a"), &b"hello\n"[..]).unwrap(),
+            VerifyStatus::Passed
+        );
+        assert!(Manifest::from_rclone(folder_path, "dropbox", &b""[..]).is_err());
+        assert!(Manifest::from_rclone(folder_path, "md5", &b"00  \xff\n"[..]).is_err());
+    }
+
+    #[test]
+    fn unrecognized_lines_are_rejected() {
+        let folder_path = Path::new("root");
+        let manifest = format!(
+            "# version: 3\n\n#note\n[a | {} | 1 | 1700000000.0]\n",
+            "0".repeat(32)
+        );
+        assert!(Manifest::from_reader(folder_path, manifest.as_bytes()).is_ok());
+        let truncated = format!("{}[b | 0123", manifest);
+        assert!(matches!(
+            Manifest::from_reader(folder_path, truncated.as_bytes()),
+            Err(Error::UnrecognizedLine { line: 5 })
+        ));
+    }
+}
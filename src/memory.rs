@@ -0,0 +1,141 @@
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// 系统可用内存低于下限或本进程的常驻内存超过上限时逐步减少同时运行的任务,
+// 内存充足后再逐步恢复; 没有设置的阈值不检查
+pub(crate) struct MemoryGuard {
+    permits: Arc<Semaphore>,
+    reduced: Arc<AtomicBool>,
+}
+
+// 内存压力的判断结果
+enum Pressure {
+    High,
+    Normal,
+    Low,
+}
+
+impl MemoryGuard {
+    pub(crate) fn start(
+        min_available: Option<u64>,
+        max_rss: Option<u64>,
+        jobs: usize,
+    ) -> Arc<MemoryGuard> {
+        let guard = Arc::new(MemoryGuard {
+            permits: Arc::new(Semaphore::new(jobs)),
+            reduced: Arc::new(AtomicBool::new(false)),
+        });
+
+        // 监视任务在所有工作任务结束后退出
+        let weak = Arc::downgrade(&guard);
+        let permits = Arc::clone(&guard.permits);
+        let reduced = Arc::clone(&guard.reduced);
+        tokio::spawn(async move {
+            let mut reserved = Vec::new();
+            loop {
+                tokio::time::sleep(CHECK_INTERVAL).await;
+                if weak.strong_count() == 0 {
+                    break;
+                }
+                // 无法读取内存用量的平台上不做限制
+                let Some(pressure) = pressure(min_available, max_rss) else {
+                    break;
+                };
+                let active = jobs - reserved.iter().map(|(_, count)| count).sum::<usize>();
+                match pressure {
+                    Pressure::High if active > 1 => {
+                        // 每次把同时运行的任务数减半, 等正在运行的任务完成后收回许可
+                        let count = active / 2;
+                        if let Ok(permit) =
+                            Arc::clone(&permits).acquire_many_owned(count as u32).await
+                        {
+                            reserved.push((permit, count));
+                        }
+                    }
+                    Pressure::Low => {
+                        reserved.pop();
+                    }
+                    _ => {}
+                }
+                reduced.store(!reserved.is_empty(), Ordering::Relaxed);
+            }
+        });
+        guard
+    }
+
+    pub(crate) async fn acquire(&self) -> Option<SemaphorePermit<'_>> {
+        self.permits.acquire().await.ok()
+    }
+
+    pub(crate) fn is_reduced(&self) -> bool {
+        self.reduced.load(Ordering::Relaxed)
+    }
+}
+
+// 任一阈值被突破时压力高; 设置的各项都明显低于阈值时压力低, 避免在阈值附近反复调整
+// 设置的各项都无法读取时返回 None
+fn pressure(min_available: Option<u64>, max_rss: Option<u64>) -> Option<Pressure> {
+    let available = min_available.and_then(|min| Some((available_memory()?, min)));
+    let rss = max_rss.and_then(|max| Some((process_rss()?, max)));
+    if available.is_none() && rss.is_none() {
+        return None;
+    }
+    if available.is_some_and(|(available, min)| available < min)
+        || rss.is_some_and(|(rss, max)| rss > max)
+    {
+        return Some(Pressure::High);
+    }
+    // 可用内存恢复到下限的两倍, 常驻内存回落到上限的3/4以下
+    if available.is_none_or(|(available, min)| available >= min.saturating_mul(2))
+        && rss.is_none_or(|(rss, max)| rss < max / 4 * 3)
+    {
+        return Some(Pressure::Low);
+    }
+    Some(Pressure::Normal)
+}
+
+// 读取 /proc/meminfo 中的 MemAvailable
+fn available_memory() -> Option<u64> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    read_kib(&meminfo, "MemAvailable:")
+}
+
+// 读取 /proc/self/status 中的 VmRSS
+fn process_rss() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    read_kib(&status, "VmRSS:")
+}
+
+fn read_kib(text: &str, key: &str) -> Option<u64> {
+    let value = text.lines().find_map(|line| line.strip_prefix(key))?;
+    let kib: u64 = value.trim().strip_suffix("kB")?.trim().parse().ok()?;
+    Some(kib * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn either_threshold_raises_pressure() {
+        if process_rss().is_none() || available_memory().is_none() {
+            return;
+        }
+        assert!(pressure(None, None).is_none());
+        assert!(matches!(pressure(None, Some(1)), Some(Pressure::High)));
+        assert!(matches!(
+            pressure(Some(u64::MAX), None),
+            Some(Pressure::High)
+        ));
+        assert!(matches!(pressure(Some(1), Some(1)), Some(Pressure::High)));
+        assert!(matches!(
+            pressure(Some(1), Some(u64::MAX)),
+            Some(Pressure::Low)
+        ));
+    }
+}
@@ -0,0 +1,84 @@
+use crate::Event;
+use std::time::{Duration, Instant};
+
+/// 根据事件统计已完成的文件数和字节数, 估计速度和剩余时间
+pub struct Progress {
+    total_files: usize,
+    total_bytes: u64,
+    done_files: usize,
+    done_bytes: u64,
+    started: Instant,
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Progress {
+            total_files: 0,
+            total_bytes: 0,
+            done_files: 0,
+            done_bytes: 0,
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Progress {
+    pub fn new() -> Progress {
+        Progress::default()
+    }
+
+    pub fn record(&mut self, event: &Event) {
+        match event {
+            Event::Planned { files, bytes } => {
+                self.total_files += files;
+                self.total_bytes += bytes;
+            }
+            Event::Hashed { bytes, .. } | Event::Verified { bytes, .. } => {
+                self.done_files += 1;
+                self.done_bytes += bytes;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn total_files(&self) -> usize {
+        self.total_files
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.total_bytes
+    }
+
+    pub fn done_files(&self) -> usize {
+        self.done_files
+    }
+
+    pub fn done_bytes(&self) -> u64 {
+        self.done_bytes
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn bytes_per_sec(&self) -> f64 {
+        let elapsed = self.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            self.done_bytes as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+
+    pub fn eta(&self) -> Option<Duration> {
+        let bytes_per_sec = self.bytes_per_sec();
+        if bytes_per_sec > 0.0 {
+            let remaining_bytes = self.total_bytes.saturating_sub(self.done_bytes);
+            Some(Duration::from_secs_f64(
+                remaining_bytes as f64 / bytes_per_sec,
+            ))
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use crate::{ContentType, Digest, Error, SkipReason};
+use std::path::{Path, PathBuf};
+
+/// 单个文件的校验结果; 多个清单的结论不同时, 事件中报告 severity 最高的一个
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[non_exhaustive]
+pub enum VerifyStatus {
+    /// 哈希与清单一致
+    Passed,
+    /// 哈希与清单不一致
+    Failed,
+    /// 清单中的文件不存在
+    Missing,
+    /// 预期会变化的文件不一致或缺失
+    Volatile,
+}
+
+impl VerifyStatus {
+    pub(crate) fn severity(&self) -> u8 {
+        match self {
+            VerifyStatus::Passed => 0,
+            VerifyStatus::Volatile => 1,
+            VerifyStatus::Missing => 2,
+            VerifyStatus::Failed => 3,
+        }
+    }
+}
+
+/// 清单中每个文件的校验结果, 按完成校验的先后排列
+#[non_exhaustive]
+pub struct VerifyReport {
+    pub results: Vec<(PathBuf, VerifyStatus)>,
+    /// 要求校验但清单中没有的文件, 只在按指定路径校验时出现
+    pub unknown: Vec<PathBuf>,
+}
+
+impl VerifyReport {
+    pub fn count(&self, status: VerifyStatus) -> usize {
+        self.results
+            .iter()
+            .filter(|(_, result_status)| *result_status == status)
+            .count()
+    }
+
+    /// 易变文件的不一致单独报告, 不算校验失败; 清单中没有的文件无法校验, 算作失败
+    pub fn is_ok(&self) -> bool {
+        self.unknown.is_empty()
+            && self
+                .results
+                .iter()
+                .all(|(_, status)| matches!(status, VerifyStatus::Passed | VerifyStatus::Volatile))
+    }
+}
+
+/// 生成和校验过程中通过 on_event 回调报告的事件, 借用的数据只在回调期间有效
+#[non_exhaustive]
+pub enum Event<'a> {
+    /// 即将计算这些文件的哈希, 可能报告多次
+    Planned { files: usize, bytes: u64 },
+    /// 计算出一个文件的哈希
+    Hashed {
+        file_path: &'a Path,
+        hash: &'a Digest,
+        bytes: u64,
+        content_type: ContentType,
+    },
+    /// 清单中没有的新文件
+    Added(&'a Path),
+    /// 大小或修改时间变化的文件
+    Changed(&'a Path),
+    /// 清单中有但已不存在的文件
+    Removed(&'a Path),
+    /// 不影响继续运行的错误
+    Warning(&'a Error),
+    /// 遍历时跳过的路径
+    Skipped {
+        file_path: &'a Path,
+        reason: SkipReason,
+    },
+    /// 校验完一个文件
+    Verified {
+        file_path: &'a Path,
+        hash: Option<&'a Digest>,
+        bytes: u64,
+        content_type: Option<ContentType>,
+        status: VerifyStatus,
+    },
+}
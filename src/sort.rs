@@ -0,0 +1,144 @@
+use crate::{Error, Result};
+use std::cmp::Ordering;
+use std::path::Path;
+
+/// 写入清单时条目的排列顺序
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[non_exhaustive]
+pub enum SortOrder {
+    #[default]
+    Bytewise,
+    Natural,
+}
+
+impl SortOrder {
+    pub fn from_name(name: &str) -> Result<SortOrder> {
+        match name {
+            "bytes" => Ok(SortOrder::Bytewise),
+            "natural" => Ok(SortOrder::Natural),
+            _ => Err(Error::UnsupportedSortOrder(name.to_string())),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SortOrder::Bytewise => "bytes",
+            SortOrder::Natural => "natural",
+        }
+    }
+
+    /// 逐个比较路径组件, 使目录中的文件总是排在一起
+    pub fn compare(&self, a: &Path, b: &Path) -> Ordering {
+        match self {
+            SortOrder::Bytewise => a.cmp(b),
+            SortOrder::Natural => {
+                let mut a = a.components();
+                let mut b = b.components();
+                loop {
+                    match (a.next(), b.next()) {
+                        (Some(x), Some(y)) => {
+                            let ordering = natural_cmp(
+                                x.as_os_str().as_encoded_bytes(),
+                                y.as_os_str().as_encoded_bytes(),
+                            );
+                            if ordering != Ordering::Equal {
+                                return ordering;
+                            }
+                        }
+                        (x, y) => return x.is_some().cmp(&y.is_some()),
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn compare_str(&self, a: &str, b: &str) -> Ordering {
+        match self {
+            SortOrder::Bytewise => a.cmp(b),
+            SortOrder::Natural => natural_cmp(a.as_bytes(), b.as_bytes()),
+        }
+    }
+}
+
+// 连续的数字按数值比较, 其他字节按字节值比较, 与系统语言设置无关
+fn natural_cmp(a: &[u8], b: &[u8]) -> Ordering {
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i].is_ascii_digit() && b[j].is_ascii_digit() {
+            let a_end = i + a[i..].iter().take_while(|c| c.is_ascii_digit()).count();
+            let b_end = j + b[j..].iter().take_while(|c| c.is_ascii_digit()).count();
+            let a_digits = trim_zeros(&a[i..a_end]);
+            let b_digits = trim_zeros(&b[j..b_end]);
+            // 去掉前导零后位数多的数值大, 位数相同时逐位比较
+            let ordering = a_digits
+                .len()
+                .cmp(&b_digits.len())
+                .then_with(|| a_digits.cmp(b_digits))
+                .then_with(|| (a_end - i).cmp(&(b_end - j)));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+            i = a_end;
+            j = b_end;
+        } else {
+            if a[i] != b[j] {
+                return a[i].cmp(&b[j]);
+            }
+            i += 1;
+            j += 1;
+        }
+    }
+    (a.len() - i).cmp(&(b.len() - j))
+}
+
+fn trim_zeros(digits: &[u8]) -> &[u8] {
+    let zeros = digits.iter().take_while(|&&c| c == b'0').count();
+    &digits[zeros..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(order: SortOrder, paths: &[&str]) -> Vec<String> {
+        let mut paths: Vec<&str> = paths.to_vec();
+        paths.sort_by(|a, b| order.compare(Path::new(a), Path::new(b)));
+        paths.into_iter().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn natural_order_compares_numbers_by_value() {
+        assert_eq!(
+            sorted(SortOrder::Natural, &["file10", "file2", "file1", "file02"]),
+            ["file1", "file2", "file02", "file10"]
+        );
+        assert_eq!(
+            sorted(SortOrder::Bytewise, &["file10", "file2", "file1"]),
+            ["file1", "file10", "file2"]
+        );
+        assert_eq!(
+            SortOrder::Natural.compare_str("a99999999999999999999999", "a100000000000000000000000"),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn natural_order_compares_path_components() {
+        // 目录中的文件排在同名前缀的文件之前, 不受分隔符字节值的影响
+        assert_eq!(
+            sorted(
+                SortOrder::Natural,
+                &["dir.txt", "dir/b", "dir/a10", "dir/a9"]
+            ),
+            ["dir/a9", "dir/a10", "dir/b", "dir.txt"]
+        );
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for order in [SortOrder::Bytewise, SortOrder::Natural] {
+            assert_eq!(SortOrder::from_name(order.name()).unwrap(), order);
+        }
+        assert!(SortOrder::from_name("locale").is_err());
+    }
+}
@@ -0,0 +1,110 @@
+use crate::walk::{visit_files, walk_files_with_events};
+use crate::{get_file_meta, Error, FileMeta, Result, WalkEvent, WalkOptions};
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use tokio::io::AsyncRead;
+use unicode_normalization::UnicodeNormalization;
+
+/// 读取文件内容的异步读取器
+pub type SourceReader = Box<dyn AsyncRead + Send + Unpin>;
+
+/// [`Source::open`] 返回的 Future
+pub type OpenFuture<'a> = Pin<Box<dyn Future<Output = Result<SourceReader>> + Send + 'a>>;
+
+/// 要计算哈希的文件的来源, 路径都以 root 开头
+pub trait Source: Send + Sync {
+    fn root(&self) -> &Path;
+
+    fn list(
+        &self,
+        walk_options: &WalkOptions,
+        on_event: &mut dyn FnMut(WalkEvent),
+    ) -> Result<Vec<PathBuf>>;
+
+    /// 边遍历边报告找到的文件, 默认在 list 完成后逐个报告
+    fn walk(&self, walk_options: &WalkOptions, on_event: &mut dyn FnMut(WalkEvent)) -> Result<()> {
+        for file_path in self.list(walk_options, on_event)? {
+            on_event(WalkEvent::File(file_path));
+        }
+        Ok(())
+    }
+
+    fn metadata(&self, file_path: &Path) -> Result<FileMeta>;
+
+    fn open<'a>(&'a self, file_path: &'a Path) -> OpenFuture<'a>;
+
+    /// 能直接从本地文件系统读取时返回文件路径
+    fn local_path<'a>(&self, _file_path: &'a Path) -> Option<&'a Path> {
+        None
+    }
+}
+
+/// 本地文件系统中的文件夹
+pub struct LocalSource {
+    root: PathBuf,
+}
+
+impl LocalSource {
+    pub fn new(root: &Path) -> LocalSource {
+        LocalSource {
+            root: root.to_path_buf(),
+        }
+    }
+}
+
+impl Source for LocalSource {
+    fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn list(
+        &self,
+        walk_options: &WalkOptions,
+        on_event: &mut dyn FnMut(WalkEvent),
+    ) -> Result<Vec<PathBuf>> {
+        walk_files_with_events(&self.root, walk_options, &mut |event| on_event(event))
+    }
+
+    fn walk(&self, walk_options: &WalkOptions, on_event: &mut dyn FnMut(WalkEvent)) -> Result<()> {
+        visit_files(&self.root, walk_options, &mut |event| on_event(event))
+    }
+
+    fn metadata(&self, file_path: &Path) -> Result<FileMeta> {
+        match get_file_meta(file_path) {
+            Err(err) if err.is_not_found() => match nfd_path(file_path) {
+                Some(nfd_path) => get_file_meta(&nfd_path),
+                None => Err(err),
+            },
+            result => result,
+        }
+    }
+
+    fn open<'a>(&'a self, file_path: &'a Path) -> OpenFuture<'a> {
+        Box::pin(async move {
+            let file = match tokio::fs::File::open(file_path).await {
+                Err(err) if err.kind() == io::ErrorKind::NotFound => match nfd_path(file_path) {
+                    Some(nfd_path) => tokio::fs::File::open(nfd_path).await,
+                    None => Err(err),
+                },
+                result => result,
+            }
+            .map_err(|err| Error::io(file_path, err))?;
+            Ok(Box::new(file) as SourceReader)
+        })
+    }
+
+    fn local_path<'a>(&self, file_path: &'a Path) -> Option<&'a Path> {
+        Some(file_path)
+    }
+}
+
+// 清单中的路径是NFC形式, 找不到文件时再尝试磁盘上可能使用的NFD形式
+fn nfd_path(file_path: &Path) -> Option<PathBuf> {
+    let path_str = file_path.to_str()?;
+    if path_str.is_ascii() {
+        return None;
+    }
+    Some(PathBuf::from(path_str.nfd().collect::<String>()))
+}
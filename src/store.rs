@@ -0,0 +1,154 @@
+use crate::manifest::temp_file_path;
+use crate::{journal_path, CompanionFormat, Error, Manifest, Result};
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// 清单的存放位置
+pub trait ManifestStore {
+    fn load(&self, folder_path: &Path) -> Result<Manifest>;
+
+    fn save(&self, manifest: &Manifest) -> Result<()>;
+}
+
+/// 保存在本地文本文件中的清单, 保存时检测读取后是否被其他进程修改
+pub struct TextFileStore {
+    hash_file_path: PathBuf,
+    // 读取时清单文件的状态, 保存前用来检测文件是否被其他进程修改
+    loaded_state: Mutex<Option<FileState>>,
+}
+
+/// 持有期间其他进程无法锁定同一个清单, 丢弃时自动解锁
+pub struct ManifestLock {
+    _file: File,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct FileState {
+    size: u64,
+    modified: SystemTime,
+}
+
+impl TextFileStore {
+    pub fn new(hash_file_path: &Path) -> TextFileStore {
+        TextFileStore {
+            hash_file_path: hash_file_path.to_path_buf(),
+            loaded_state: Mutex::new(None),
+        }
+    }
+
+    pub fn hash_file_path(&self) -> &Path {
+        &self.hash_file_path
+    }
+
+    /// 在清单旁的锁文件上加排他锁, 防止多个进程同时改写同一个清单
+    pub fn lock(&self) -> Result<ManifestLock> {
+        let lock_file_path = lock_path(&self.hash_file_path);
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_file_path)
+            .map_err(|err| Error::io(&lock_file_path, err))?;
+        file.lock().map_err(|err| Error::io(&lock_file_path, err))?;
+        Ok(ManifestLock { _file: file })
+    }
+
+    fn file_state(&self) -> Result<Option<FileState>> {
+        match fs::metadata(&self.hash_file_path) {
+            Ok(metadata) => Ok(Some(FileState {
+                size: metadata.len(),
+                modified: metadata
+                    .modified()
+                    .map_err(|err| Error::io(&self.hash_file_path, err))?,
+            })),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(Error::io(&self.hash_file_path, err)),
+        }
+    }
+}
+
+impl ManifestStore for TextFileStore {
+    fn load(&self, folder_path: &Path) -> Result<Manifest> {
+        let state = self.file_state()?;
+        let manifest = Manifest::read(folder_path, &self.hash_file_path)?;
+        *self.loaded_state.lock().unwrap() = state;
+        Ok(manifest)
+    }
+
+    fn save(&self, manifest: &Manifest) -> Result<()> {
+        let mut loaded_state = self.loaded_state.lock().unwrap();
+        if loaded_state.is_some() && self.file_state()? != *loaded_state {
+            return Err(Error::ManifestConflict(self.hash_file_path.clone()));
+        }
+        manifest.write(&self.hash_file_path)?;
+        *loaded_state = self.file_state()?;
+        Ok(())
+    }
+}
+
+pub(crate) fn lock_path(hash_file_path: &Path) -> PathBuf {
+    let mut file_name = OsString::from(".");
+    file_name.push(hash_file_path.file_name().unwrap_or_default());
+    file_name.push(".lock");
+    hash_file_path.with_file_name(file_name)
+}
+
+/// 清单本身和运行时在它旁边创建的文件, 清单位于被遍历的文件夹中时不能被当作普通文件记录
+pub fn sidecar_paths(hash_file_path: &Path) -> Vec<PathBuf> {
+    let mut file_paths = vec![
+        hash_file_path.to_path_buf(),
+        lock_path(hash_file_path),
+        journal_path(hash_file_path),
+        temp_file_path(hash_file_path),
+    ];
+    for companion_format in [CompanionFormat::Sha256sum, CompanionFormat::Sfv] {
+        let companion_path = hash_file_path.with_extension(companion_format.extension());
+        file_paths.push(temp_file_path(&companion_path));
+        file_paths.push(companion_path);
+    }
+    file_paths
+}
+
+/// 从HTTP地址读取上游发布的清单, 只能读取不能写入
+pub struct HttpStore {
+    url: String,
+}
+
+impl HttpStore {
+    pub fn new(url: &str) -> HttpStore {
+        HttpStore {
+            url: url.to_string(),
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+}
+
+impl ManifestStore for HttpStore {
+    fn load(&self, folder_path: &Path) -> Result<Manifest> {
+        let http_error = |err: ureq::Error| Error::Http {
+            url: self.url.clone(),
+            message: err.to_string(),
+        };
+        let response = ureq::get(&self.url).call().map_err(http_error)?;
+        // 边下载边解析, 不受整体读取时的大小限制, 也不会替换非UTF-8的字节
+        let reader = BufReader::new(response.into_body().into_reader());
+        Manifest::from_reader(folder_path, reader).map_err(|err| match err {
+            Error::Read(err) => Error::Http {
+                url: self.url.clone(),
+                message: err.to_string(),
+            },
+            err => err,
+        })
+    }
+
+    fn save(&self, _manifest: &Manifest) -> Result<()> {
+        Err(Error::ReadOnlyStore)
+    }
+}
@@ -0,0 +1,248 @@
+use crate::manifest::temp_file_path;
+use crate::store::lock_path;
+use crate::{journal_path, Error, Result};
+use glob::{MatchOptions, Pattern};
+use std::cmp::Reverse;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 展开输出路径模板中的 {folder_name}、{date} 和 {time}, 日期和时间使用UTC
+pub fn expand_template(template: &str, folder_path: &Path, now: SystemTime) -> Result<String> {
+    let secs = now
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let (year, month, day) = civil_from_days(secs / 86400);
+    let secs_of_day = secs % 86400;
+
+    let invalid = || Error::InvalidTemplate(template.to_string());
+    let mut expanded = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+        let end = start + rest[start..].find('}').ok_or_else(invalid)?;
+        match &rest[start + 1..end] {
+            "folder_name" => expanded.push_str(&folder_name(folder_path)),
+            "date" => {
+                let _ = write!(expanded, "{:04}-{:02}-{:02}", year, month, day);
+            }
+            "time" => {
+                let _ = write!(
+                    expanded,
+                    "{:02}{:02}{:02}",
+                    secs_of_day / 3600,
+                    secs_of_day / 60 % 60,
+                    secs_of_day % 60
+                );
+            }
+            _ => return Err(invalid()),
+        }
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// 删除输出目录中按同一模板生成的旧文件, 保留最新的 keep_last 个或 keep_days 天内的文件
+/// 被删除的清单旁留下的锁文件, 预写日志和临时文件一起删除
+pub fn prune_outputs(
+    template: &str,
+    folder_path: &Path,
+    keep_last: Option<usize>,
+    keep_days: Option<u64>,
+    now: SystemTime,
+) -> Result<Vec<PathBuf>> {
+    if keep_last.is_none() && keep_days.is_none() {
+        return Ok(Vec::new());
+    }
+
+    // 只支持文件名中的模板, 目录部分必须是固定的
+    let template_path = Path::new(template);
+    let invalid = || Error::InvalidTemplate(template.to_string());
+    let dir = match template_path.parent() {
+        Some(dir) if dir.to_string_lossy().contains('{') => return Err(invalid()),
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let file_name = template_path
+        .file_name()
+        .and_then(|file_name| file_name.to_str())
+        .ok_or_else(invalid)?;
+    let pattern =
+        Pattern::new(&template_pattern(file_name, folder_path)?).map_err(|_| invalid())?;
+    let match_options = MatchOptions {
+        require_literal_leading_dot: true,
+        ..MatchOptions::new()
+    };
+
+    let mut outputs = Vec::new();
+    for entry in fs::read_dir(dir).map_err(|err| Error::io(dir, err))? {
+        let entry = entry.map_err(|err| Error::io(dir, err))?;
+        let path = entry.path();
+        if !pattern.matches_with(&entry.file_name().to_string_lossy(), match_options) {
+            continue;
+        }
+        let metadata = entry.metadata().map_err(|err| Error::io(&path, err))?;
+        if !metadata.is_file() {
+            continue;
+        }
+        let modified = metadata.modified().map_err(|err| Error::io(&path, err))?;
+        outputs.push((modified, path));
+    }
+
+    // 从新到旧排序, 满足任意一个保留条件的文件都会保留
+    outputs.sort_by_key(|(modified, _)| Reverse(*modified));
+    let mut removed = Vec::new();
+    for (index, (modified, path)) in outputs.into_iter().enumerate() {
+        let kept_by_count = keep_last.is_some_and(|keep_last| index < keep_last);
+        let kept_by_age = keep_days.is_some_and(|keep_days| {
+            now.duration_since(modified)
+                .map_or(true, |age| age.as_secs() < keep_days * 86400)
+        });
+        if kept_by_count || kept_by_age {
+            continue;
+        }
+        fs::remove_file(&path).map_err(|err| Error::io(&path, err))?;
+        for sidecar_path in [lock_path(&path), journal_path(&path), temp_file_path(&path)] {
+            match fs::remove_file(&sidecar_path) {
+                Ok(()) => removed.push(sidecar_path),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(Error::io(&sidecar_path, err)),
+            }
+        }
+        removed.push(path);
+    }
+    Ok(removed)
+}
+
+// 按展开模板的方式生成匹配模式: 文件夹名按字面匹配, 日期和时间只匹配对应位数的数字
+// 不能把占位符都替换为 *, 否则 {folder_name}-{date}.xxh 会匹配目录中无关的文件
+fn template_pattern(template: &str, folder_path: &Path) -> Result<String> {
+    let invalid = || Error::InvalidTemplate(template.to_string());
+    let digits = |count| "[0-9]".repeat(count);
+    let mut pattern = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        pattern.push_str(&Pattern::escape(&rest[..start]));
+        let end = start + rest[start..].find('}').ok_or_else(invalid)?;
+        match &rest[start + 1..end] {
+            "folder_name" => pattern.push_str(&Pattern::escape(&folder_name(folder_path))),
+            "date" => {
+                let _ = write!(pattern, "{}-{}-{}", digits(4), digits(2), digits(2));
+            }
+            "time" => pattern.push_str(&digits(6)),
+            _ => return Err(invalid()),
+        }
+        rest = &rest[end + 1..];
+    }
+    pattern.push_str(&Pattern::escape(rest));
+    Ok(pattern)
+}
+
+// "." 之类没有文件名的路径使用规范化后的目录名
+fn folder_name(folder_path: &Path) -> String {
+    let canonical_path = fs::canonicalize(folder_path).ok();
+    folder_path
+        .file_name()
+        .or_else(|| canonical_path.as_deref().and_then(Path::file_name))
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+// 把1970-01-01以来的天数转换为公历日期
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestTree;
+    use std::fs::File;
+    use std::time::Duration;
+
+    // 2024-01-02 03:04:05 UTC
+    const NOW: u64 = 1704164645;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn touch(tree: &TestTree, relative_path: &str, modified: SystemTime) {
+        let file_path = tree.file(relative_path, relative_path);
+        File::options()
+            .write(true)
+            .open(&file_path)
+            .and_then(|file| file.set_modified(modified))
+            .unwrap();
+    }
+
+    #[test]
+    fn expands_placeholders() {
+        let tree = TestTree::new();
+        let folder_path = tree
+            .file("photos/a.jpg", "a")
+            .parent()
+            .unwrap()
+            .to_path_buf();
+        let expanded =
+            expand_template("out/{folder_name}-{date}-{time}.xxh", &folder_path, at(NOW));
+        assert_eq!(expanded.unwrap(), "out/photos-2024-01-02-030405.xxh");
+        assert!(expand_template("{unknown}.xxh", &folder_path, at(NOW)).is_err());
+        assert!(expand_template("{date.xxh", &folder_path, at(NOW)).is_err());
+    }
+
+    #[test]
+    fn prune_only_matches_the_expanded_template() {
+        let tree = TestTree::new();
+        let folder_path = tree.join("photos");
+        for (index, date) in ["2024-01-01", "2023-12-31", "2023-12-30"]
+            .iter()
+            .enumerate()
+        {
+            touch(
+                &tree,
+                &format!("photos-{}.xxh", date),
+                at(NOW - index as u64 * 86400),
+            );
+        }
+        // 另一个文件夹的清单和名称相似的无关文件都不能被删除
+        touch(&tree, "videos-2023-01-01.xxh", at(0));
+        touch(&tree, "photos-backup.xxh", at(0));
+        touch(&tree, ".photos-2023-12-30.xxh.lock", at(0));
+        touch(&tree, ".photos-2023-12-30.xxh.journal", at(0));
+
+        let template = tree.join("{folder_name}-{date}.xxh");
+        let removed = prune_outputs(
+            &template.to_string_lossy(),
+            &folder_path,
+            Some(2),
+            None,
+            at(NOW),
+        )
+        .unwrap();
+        assert_eq!(
+            removed,
+            [
+                tree.join(".photos-2023-12-30.xxh.lock"),
+                tree.join(".photos-2023-12-30.xxh.journal"),
+                tree.join("photos-2023-12-30.xxh"),
+            ]
+        );
+        assert!(tree.join("photos-2024-01-01.xxh").exists());
+        assert!(tree.join("photos-2023-12-31.xxh").exists());
+        assert!(tree.join("videos-2023-01-01.xxh").exists());
+        assert!(tree.join("photos-backup.xxh").exists());
+    }
+}
@@ -0,0 +1,176 @@
+//! 测试用的临时目录树和标准清单, 只在测试和启用 testing 特性时编译
+
+use crate::{HashGenerator, Manifest, Verifier, VerifyReport, VerifyStatus};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// 固定内容的目录树, 覆盖空文件, 全零文件, 多级目录, 非ASCII文件名和非UTF-8内容
+pub const GOLDEN_FILES: &[(&str, &[u8])] = &[
+    ("empty", b""),
+    ("hello.txt", b"hello world\n"),
+    ("nested/dir/data.bin", b"\x00\x01\x02\x03\xff\xfe\xfd\xfc"),
+    ("unicode/ünïcödé.txt", "你好\n".as_bytes()),
+    ("zeros.bin", &[0; 4096]),
+];
+
+/// GOLDEN_FILES 对应的清单, 生成结果与它不同说明哈希或清单格式发生了变化
+pub const GOLDEN_MANIFEST: &str = "\
+# version: 3
+# algorithm: xxh3-128
+# timestamps: no
+[empty | 99aa06d3014798d86001c324468d497f]
+[hello.txt | eefac9d87100cd1336b2e733a5484425]
+[nested/dir/data.bin | 8942e1c7e1c68f973bfe791fd93710e8]
+[unicode/ünïcödé.txt | 9d513abd81eceaef1e23c9aaaf4f14a2]
+[zeros.bin | 3ee8dc4f9e7ee49593d76fe148c689ba]
+";
+
+/// 集成测试使用的临时目录树, 丢弃时删除整个目录
+/// 测试辅助函数出错时直接 panic, 使测试失败并显示原因
+pub struct TestTree {
+    root: PathBuf,
+}
+
+impl TestTree {
+    pub fn new() -> TestTree {
+        let root = env::temp_dir().join(format!("xxhash_verify-{}", Uuid::new_v4()));
+        fs::create_dir_all(&root)
+            .unwrap_or_else(|err| panic!("创建临时目录[{}]失败: {}", root.display(), err));
+        TestTree { root }
+    }
+
+    /// 按 GOLDEN_FILES 创建目录树
+    pub fn golden() -> TestTree {
+        let tree = TestTree::new();
+        for (relative_path, contents) in GOLDEN_FILES {
+            tree.file(relative_path, contents);
+        }
+        tree
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn join(&self, relative_path: &str) -> PathBuf {
+        self.root.join(relative_path)
+    }
+
+    /// 写入文件并自动创建上级目录, 返回文件的完整路径
+    pub fn file(&self, relative_path: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+        let file_path = self.join(relative_path);
+        if let Some(parent) = file_path.parent() {
+            fs::create_dir_all(parent)
+                .unwrap_or_else(|err| panic!("创建目录[{}]失败: {}", parent.display(), err));
+        }
+        fs::write(&file_path, contents)
+            .unwrap_or_else(|err| panic!("写入[{}]失败: {}", file_path.display(), err));
+        file_path
+    }
+
+    /// 翻转文件中间的一个字节, 文件大小不变, 空文件改为写入一个字节
+    pub fn corrupt(&self, relative_path: &str) {
+        let file_path = self.join(relative_path);
+        let flip = || -> io::Result<()> {
+            let mut file = OpenOptions::new().read(true).write(true).open(&file_path)?;
+            let len = file.metadata()?.len();
+            let mut byte = [0];
+            if len > 0 {
+                file.seek(SeekFrom::Start(len / 2))?;
+                file.read_exact(&mut byte)?;
+                file.seek(SeekFrom::Start(len / 2))?;
+            }
+            file.write_all(&[!byte[0]])
+        };
+        flip().unwrap_or_else(|err| panic!("改写[{}]失败: {}", file_path.display(), err));
+    }
+
+    pub fn truncate(&self, relative_path: &str, len: u64) {
+        let file_path = self.join(relative_path);
+        OpenOptions::new()
+            .write(true)
+            .open(&file_path)
+            .and_then(|file| file.set_len(len))
+            .unwrap_or_else(|err| panic!("截断[{}]失败: {}", file_path.display(), err));
+    }
+
+    pub fn remove(&self, relative_path: &str) {
+        let file_path = self.join(relative_path);
+        fs::remove_file(&file_path)
+            .unwrap_or_else(|err| panic!("删除[{}]失败: {}", file_path.display(), err));
+    }
+
+    pub async fn generate(&self) -> Manifest {
+        self.generate_with(HashGenerator::new()).await
+    }
+
+    pub async fn generate_with(&self, generator: HashGenerator) -> Manifest {
+        generator
+            .run(&self.root, |_| {})
+            .await
+            .unwrap_or_else(|err| panic!("计算[{}]的哈希失败: {}", self.root.display(), err))
+    }
+
+    /// 解析 GOLDEN_MANIFEST, 条目指向这个目录树
+    pub fn golden_manifest(&self) -> Manifest {
+        Manifest::from_reader(&self.root, GOLDEN_MANIFEST.as_bytes())
+            .unwrap_or_else(|err| panic!("解析标准清单失败: {}", err))
+    }
+
+    pub async fn verify(&self, manifest: &Manifest) -> VerifyReport {
+        self.verify_with(&Verifier::new(), manifest).await
+    }
+
+    pub async fn verify_with(&self, verifier: &Verifier, manifest: &Manifest) -> VerifyReport {
+        verifier
+            .run(manifest, |_| {})
+            .await
+            .unwrap_or_else(|err| panic!("校验[{}]失败: {}", self.root.display(), err))
+    }
+
+    /// 断言报告中某个文件的校验结果
+    pub fn assert_status(
+        &self,
+        report: &VerifyReport,
+        relative_path: &str,
+        expected: VerifyStatus,
+    ) {
+        let file_path = self.join(relative_path);
+        match report.results.iter().find(|(path, _)| *path == file_path) {
+            Some((_, status)) => {
+                assert_eq!(*status, expected, "[{}]的校验结果不符合预期", relative_path)
+            }
+            None => panic!("校验报告中没有[{}]", relative_path),
+        }
+    }
+
+    /// 断言除列出的文件外其他文件都通过校验
+    pub fn assert_passed_except(&self, report: &VerifyReport, relative_paths: &[&str]) {
+        let excluded: Vec<PathBuf> = relative_paths.iter().map(|path| self.join(path)).collect();
+        for (file_path, status) in &report.results {
+            if !excluded.contains(file_path) {
+                assert_eq!(
+                    *status,
+                    VerifyStatus::Passed,
+                    "[{}]没有通过校验",
+                    file_path.display()
+                );
+            }
+        }
+    }
+}
+
+impl Default for TestTree {
+    fn default() -> Self {
+        TestTree::new()
+    }
+}
+
+impl Drop for TestTree {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
@@ -0,0 +1,454 @@
+use crate::hash::{
+    abort_all_async_tasks, await_all_async_tasks, hash_source_file, spawn_feeder, spawn_workers,
+    BufferSizes, HashOutput, PoolOptions,
+};
+use crate::walk::{matches_pattern, parse_pattern};
+use crate::{
+    create_hasher, Digest, Error, Event, LocalSource, Manifest, Result, Source, VerifyReport,
+    VerifyStatus, DEFAULT_BUFFER_SIZE,
+};
+use glob::Pattern;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// 按清单校验文件, 通过构建方法设置并发数, 缓冲区大小等选项
+pub struct Verifier {
+    jobs: usize,
+    buffer_size: usize,
+    min_free_memory: Option<u64>,
+    max_rss: Option<u64>,
+    volatile: Vec<Pattern>,
+}
+
+impl Default for Verifier {
+    fn default() -> Self {
+        Verifier {
+            jobs: 16,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            min_free_memory: None,
+            max_rss: None,
+            volatile: Vec::new(),
+        }
+    }
+}
+
+impl Verifier {
+    pub fn new() -> Verifier {
+        Verifier::default()
+    }
+
+    pub fn jobs(mut self, jobs: usize) -> Verifier {
+        self.jobs = jobs;
+        self
+    }
+
+    pub fn buffer_size(mut self, buffer_size: usize) -> Verifier {
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    /// 系统可用内存低于该值时减少同时校验的文件数并改用较小的缓冲区
+    pub fn min_free_memory(mut self, bytes: u64) -> Verifier {
+        self.min_free_memory = Some(bytes);
+        self
+    }
+
+    /// 本进程的常驻内存超过该值时同样减少同时校验的文件数
+    pub fn max_rss(mut self, bytes: u64) -> Verifier {
+        self.max_rss = Some(bytes);
+        self
+    }
+
+    /// 匹配的文件预期会变化, 不一致或缺失时单独报告
+    pub fn volatile(mut self, pattern: &str) -> Result<Verifier> {
+        self.volatile.push(parse_pattern(pattern)?);
+        Ok(self)
+    }
+
+    fn pool_options(&self) -> PoolOptions {
+        PoolOptions {
+            jobs: self.jobs,
+            buffer_size: self.buffer_size,
+            min_free_memory: self.min_free_memory,
+            max_rss: self.max_rss,
+        }
+    }
+
+    fn is_volatile(&self, manifest: &Manifest, file_path: &Path) -> bool {
+        let relative_path = manifest.relative_path(file_path);
+        self.volatile
+            .iter()
+            .any(|pattern| matches_pattern(pattern, relative_path))
+    }
+
+    /// 按清单校验清单文件夹中的文件
+    pub async fn run(
+        &self,
+        manifest: &Manifest,
+        on_event: impl FnMut(Event<'_>),
+    ) -> Result<VerifyReport> {
+        let source = Arc::new(LocalSource::new(manifest.folder_path()));
+        self.run_source(source, manifest, on_event).await
+    }
+
+    pub async fn run_source(
+        &self,
+        source: Arc<dyn Source>,
+        manifest: &Manifest,
+        on_event: impl FnMut(Event<'_>),
+    ) -> Result<VerifyReport> {
+        let mut reports = self
+            .run_many_sources(&[(source, manifest)], on_event)
+            .await?;
+        Ok(reports.remove(0))
+    }
+
+    /// 同时校验多个清单, 返回的报告与清单一一对应
+    pub async fn run_many(
+        &self,
+        manifests: &[Manifest],
+        on_event: impl FnMut(Event<'_>),
+    ) -> Result<Vec<VerifyReport>> {
+        let sources: Vec<(Arc<dyn Source>, &Manifest)> = manifests
+            .iter()
+            .map(|manifest| {
+                let source: Arc<dyn Source> = Arc::new(LocalSource::new(manifest.folder_path()));
+                (source, manifest)
+            })
+            .collect();
+        self.run_many_sources(&sources, on_event).await
+    }
+
+    /// 从通道中逐个接收要校验的文件, 所有文件共用一个工作任务池, 通道关闭后返回报告
+    /// 清单中没有的文件报告警告并记入报告的 unknown
+    pub async fn run_paths_stream(
+        &self,
+        manifest: &Manifest,
+        mut file_paths: mpsc::Receiver<PathBuf>,
+        mut on_event: impl FnMut(Event<'_>),
+    ) -> Result<VerifyReport> {
+        // 提前检查算法是否已注册
+        create_hasher(manifest.algorithm())?;
+
+        let source: Arc<dyn Source> = Arc::new(LocalSource::new(manifest.folder_path()));
+        let algorithm = Arc::new(manifest.algorithm().to_string());
+        let (tx, mut rx) = mpsc::channel(64);
+        let (job_tx, handles) = spawn_workers(
+            self.pool_options(),
+            tx,
+            move |(index, file_path, source, algorithm): VerifyJob, buffers| async move {
+                verify_file(&*source, &file_path, &algorithm, buffers)
+                    .await
+                    .map(|output| (index, output))
+            },
+        );
+
+        // 通道关闭后丢弃任务队列的发送端, 工作任务处理完剩余的任务后退出
+        let mut job_tx = Some(job_tx);
+        let mut receiving = true;
+        // 等待任务队列空出位置的文件, 此时不接收新的路径, 但继续接收校验结果
+        let mut pending = None;
+        let mut planned: Vec<(PathBuf, Digest)> = Vec::new();
+        let mut report = VerifyReport {
+            results: Vec::new(),
+            unknown: Vec::new(),
+        };
+        let mut verifying = true;
+
+        while verifying {
+            let result = tokio::select! {
+                permit = async { job_tx.as_ref().unwrap().reserve().await }, if pending.is_some() => {
+                    if let Ok(permit) = permit {
+                        permit.send(pending.take().unwrap());
+                    }
+                    Ok(())
+                }
+                file_path = file_paths.recv(), if receiving && pending.is_none() => {
+                    match file_path {
+                        Some(file_path) => match manifest.get(&file_path) {
+                            Some(record) => {
+                                on_event(Event::Planned {
+                                    files: 1,
+                                    bytes: record.meta.map_or(0, |meta| meta.size),
+                                });
+                                planned.push((file_path.clone(), record.hash.clone()));
+                                pending = Some((
+                                    planned.len() - 1,
+                                    file_path,
+                                    Arc::clone(&source),
+                                    Arc::clone(&algorithm),
+                                ));
+                            }
+                            None => {
+                                on_event(Event::Warning(&Error::MissingHash(file_path.clone())));
+                                report.unknown.push(file_path);
+                            }
+                        },
+                        None => receiving = false,
+                    }
+                    Ok(())
+                }
+                result = rx.recv() => match result {
+                    Some(Ok((index, output))) => {
+                        let (file_path, expected) = &planned[index];
+                        let mut status = match &output {
+                            Some(output) if output.hash == *expected => VerifyStatus::Passed,
+                            Some(_) => VerifyStatus::Failed,
+                            None => VerifyStatus::Missing,
+                        };
+                        if status != VerifyStatus::Passed && self.is_volatile(manifest, file_path) {
+                            status = VerifyStatus::Volatile;
+                        }
+                        report.results.push((file_path.clone(), status));
+                        on_event(Event::Verified {
+                            file_path,
+                            hash: output.as_ref().map(|output| &output.hash),
+                            bytes: output.as_ref().map_or(0, |output| output.bytes),
+                            content_type: output.as_ref().map(|output| output.content_type),
+                            status,
+                        });
+                        Ok(())
+                    }
+                    Some(Err(err)) => Err(err),
+                    None => {
+                        verifying = false;
+                        Ok(())
+                    }
+                },
+            };
+            if let Err(err) = result {
+                abort_all_async_tasks(&handles);
+                return Err(err);
+            }
+            if !receiving && pending.is_none() {
+                job_tx = None;
+            }
+        }
+
+        // 等待所有异步任务完成
+        await_all_async_tasks(handles).await?;
+
+        Ok(report)
+    }
+
+    /// 多个清单覆盖同一文件时只计算一次哈希, 结果分别计入每个清单的报告
+    pub async fn run_many_sources(
+        &self,
+        manifests: &[(Arc<dyn Source>, &Manifest)],
+        mut on_event: impl FnMut(Event<'_>),
+    ) -> Result<Vec<VerifyReport>> {
+        // 提前检查清单使用的算法是否已注册
+        for (_, manifest) in manifests {
+            create_hasher(manifest.algorithm())?;
+        }
+
+        // 按路径和算法合并各清单的条目, 记录每个文件对应的清单和预期哈希
+        let mut file_indexes: HashMap<(&Path, &str), usize> = HashMap::new();
+        let mut files: Vec<CoveredFile> = Vec::new();
+        for (manifest_index, (source, manifest)) in manifests.iter().enumerate() {
+            for (file_path, record) in manifest.iter() {
+                let key = (file_path.as_path(), manifest.algorithm());
+                let index = *file_indexes.entry(key).or_insert_with(|| {
+                    files.push(CoveredFile {
+                        file_path: file_path.clone(),
+                        source: Arc::clone(source),
+                        algorithm: Arc::new(manifest.algorithm().to_string()),
+                        size: record.meta.map(|meta| meta.size),
+                        expected: Vec::new(),
+                    });
+                    files.len() - 1
+                });
+                files[index]
+                    .expected
+                    .push((manifest_index, record.hash.clone()));
+            }
+        }
+
+        let (tx, mut rx) = mpsc::channel(64);
+        let (job_tx, mut handles) = spawn_workers(
+            self.pool_options(),
+            tx,
+            move |(index, file_path, source, algorithm): VerifyJob, buffers| async move {
+                verify_file(&*source, &file_path, &algorithm, buffers)
+                    .await
+                    .map(|output| (index, output))
+            },
+        );
+
+        // 清单没有记录大小时读取文件的元数据
+        let bytes = files
+            .iter()
+            .map(|file| match file.size {
+                Some(size) => size,
+                None => file
+                    .source
+                    .metadata(&file.file_path)
+                    .map_or(0, |meta| meta.size),
+            })
+            .sum();
+        on_event(Event::Planned {
+            files: files.len(),
+            bytes,
+        });
+
+        let verify_jobs = files
+            .iter()
+            .enumerate()
+            .map(|(index, file)| {
+                (
+                    index,
+                    file.file_path.clone(),
+                    Arc::clone(&file.source),
+                    Arc::clone(&file.algorithm),
+                )
+            })
+            .collect();
+        handles.push(spawn_feeder(job_tx, verify_jobs));
+
+        // 从通道接收校验结果
+        let mut reports: Vec<VerifyReport> = manifests
+            .iter()
+            .map(|_| VerifyReport {
+                results: Vec::new(),
+                unknown: Vec::new(),
+            })
+            .collect();
+        while let Some(result) = rx.recv().await {
+            let (index, output) = match result {
+                Ok(result) => result,
+                Err(err) => {
+                    abort_all_async_tasks(&handles);
+                    return Err(err);
+                }
+            };
+            let file = &files[index];
+            let mut event_status = VerifyStatus::Passed;
+            for (manifest_index, expected) in &file.expected {
+                let mut status = match &output {
+                    Some(output) if output.hash == *expected => VerifyStatus::Passed,
+                    Some(_) => VerifyStatus::Failed,
+                    None => VerifyStatus::Missing,
+                };
+                if status != VerifyStatus::Passed
+                    && self.is_volatile(manifests[*manifest_index].1, &file.file_path)
+                {
+                    status = VerifyStatus::Volatile;
+                }
+                // 各清单的结论不同时报告最严重的一个
+                if status.severity() > event_status.severity() {
+                    event_status = status;
+                }
+                reports[*manifest_index]
+                    .results
+                    .push((file.file_path.clone(), status));
+            }
+            on_event(Event::Verified {
+                file_path: &file.file_path,
+                hash: output.as_ref().map(|output| &output.hash),
+                bytes: output.as_ref().map_or(0, |output| output.bytes),
+                content_type: output.as_ref().map(|output| output.content_type),
+                status: event_status,
+            });
+        }
+
+        // 等待所有异步任务完成
+        await_all_async_tasks(handles).await?;
+
+        Ok(reports)
+    }
+}
+
+type VerifyJob = (usize, PathBuf, Arc<dyn Source>, Arc<String>);
+
+// 被一个或多个清单覆盖的文件
+struct CoveredFile {
+    file_path: PathBuf,
+    source: Arc<dyn Source>,
+    algorithm: Arc<String>,
+    size: Option<u64>,
+    expected: Vec<(usize, Digest)>,
+}
+
+// 文件不存在时返回 None
+async fn verify_file(
+    source: &dyn Source,
+    file_path: &Path,
+    algorithm: &str,
+    buffers: BufferSizes,
+) -> Result<Option<HashOutput>> {
+    match hash_source_file(source, file_path, algorithm, &[], buffers).await {
+        Ok(output) => Ok(Some(output)),
+        Err(err) if err.is_not_found() => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TestTree, GOLDEN_FILES};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn golden_tree_passes_against_golden_manifest() {
+        let tree = TestTree::golden();
+        let report = tree.verify(&tree.golden_manifest()).await;
+        assert_eq!(report.results.len(), GOLDEN_FILES.len());
+        tree.assert_passed_except(&report, &[]);
+        assert!(report.is_ok());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn changed_and_missing_files_are_reported() {
+        let tree = TestTree::golden();
+        tree.corrupt("hello.txt");
+        tree.truncate("zeros.bin", 100);
+        tree.remove("nested/dir/data.bin");
+        tree.corrupt("empty");
+        let report = tree.verify(&tree.golden_manifest()).await;
+        tree.assert_status(&report, "hello.txt", VerifyStatus::Failed);
+        tree.assert_status(&report, "zeros.bin", VerifyStatus::Failed);
+        tree.assert_status(&report, "empty", VerifyStatus::Failed);
+        tree.assert_status(&report, "nested/dir/data.bin", VerifyStatus::Missing);
+        tree.assert_passed_except(
+            &report,
+            &["hello.txt", "zeros.bin", "empty", "nested/dir/data.bin"],
+        );
+        assert_eq!(report.count(VerifyStatus::Failed), 3);
+        assert!(!report.is_ok());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn volatile_changes_do_not_fail() {
+        let tree = TestTree::golden();
+        tree.corrupt("hello.txt");
+        let verifier = Verifier::new().volatile("*.txt").unwrap();
+        let report = tree.verify_with(&verifier, &tree.golden_manifest()).await;
+        tree.assert_status(&report, "hello.txt", VerifyStatus::Volatile);
+        tree.assert_passed_except(&report, &["hello.txt"]);
+        assert!(report.is_ok());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn streamed_paths_share_one_pool_and_fail_on_unknown() {
+        let tree = TestTree::golden();
+        tree.corrupt("zeros.bin");
+        let (tx, rx) = mpsc::channel(4);
+        let paths = ["hello.txt", "zeros.bin", "not-in-manifest"].map(|path| tree.join(path));
+        tokio::spawn(async move {
+            for path in paths {
+                tx.send(path).await.unwrap();
+            }
+        });
+        let report = Verifier::new()
+            .run_paths_stream(&tree.golden_manifest(), rx, |_| {})
+            .await
+            .unwrap();
+        assert_eq!(report.results.len(), 2);
+        tree.assert_status(&report, "hello.txt", VerifyStatus::Passed);
+        tree.assert_status(&report, "zeros.bin", VerifyStatus::Failed);
+        assert_eq!(report.unknown, vec![tree.join("not-in-manifest")]);
+        assert!(!report.is_ok());
+    }
+}
@@ -0,0 +1,397 @@
+use crate::{Error, Result};
+use glob::{MatchOptions, Pattern};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 遍历时跳过文件或目录的原因
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[non_exhaustive]
+pub enum SkipReason {
+    Hidden,
+    Excluded,
+    NotIncluded,
+    Symlink,
+    SpecialFile,
+}
+
+impl SkipReason {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SkipReason::Hidden => "hidden",
+            SkipReason::Excluded => "excluded",
+            SkipReason::NotIncluded => "not_included",
+            SkipReason::Symlink => "symlink",
+            SkipReason::SpecialFile => "special_file",
+        }
+    }
+}
+
+/// 遍历目录时报告的事件
+#[non_exhaustive]
+pub enum WalkEvent {
+    /// 找到一个文件
+    File(PathBuf),
+    /// 非严格模式下无法读取的路径
+    Warning(Error),
+    /// 被跳过的路径
+    Skipped { path: PathBuf, reason: SkipReason },
+}
+
+/// 遍历目录的选项, 通过构建方法设置
+#[derive(Clone)]
+pub struct WalkOptions {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    // 按完整路径排除的文件: 上级目录的真实路径和文件名
+    excluded_paths: Vec<(PathBuf, OsString)>,
+    follow_symlinks: bool,
+    skip_hidden: bool,
+    strict: bool,
+}
+
+impl Default for WalkOptions {
+    fn default() -> Self {
+        WalkOptions {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            excluded_paths: Vec::new(),
+            follow_symlinks: true,
+            skip_hidden: false,
+            strict: false,
+        }
+    }
+}
+
+impl WalkOptions {
+    pub fn new() -> WalkOptions {
+        WalkOptions::default()
+    }
+
+    pub fn include(mut self, pattern: &str) -> Result<WalkOptions> {
+        self.include.push(parse_pattern(pattern)?);
+        Ok(self)
+    }
+
+    pub fn exclude(mut self, pattern: &str) -> Result<WalkOptions> {
+        self.exclude.push(parse_pattern(pattern)?);
+        Ok(self)
+    }
+
+    /// 排除一个具体的文件, 例如位于被遍历文件夹中的清单和它旁边的锁文件
+    /// 文件可以还不存在; 按上级目录的真实路径比较, 与传入的是相对路径还是绝对路径无关
+    pub fn exclude_path(mut self, file_path: &Path) -> WalkOptions {
+        if let Some(file_name) = file_path.file_name() {
+            let parent = parent_dir(file_path);
+            let parent = fs::canonicalize(parent).unwrap_or_else(|_| parent.to_path_buf());
+            self.excluded_paths.push((parent, file_name.to_os_string()));
+        }
+        self
+    }
+
+    pub fn follow_symlinks(mut self, follow_symlinks: bool) -> WalkOptions {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    pub fn skip_hidden(mut self, skip_hidden: bool) -> WalkOptions {
+        self.skip_hidden = skip_hidden;
+        self
+    }
+
+    pub fn strict(mut self, strict: bool) -> WalkOptions {
+        self.strict = strict;
+        self
+    }
+
+    fn is_excluded(&self, relative_path: &Path) -> bool {
+        self.exclude
+            .iter()
+            .any(|pattern| matches_pattern(pattern, relative_path))
+    }
+
+    /// 先比较文件名, 只有文件名相同时才解析上级目录
+    pub fn is_excluded_path(&self, path: &Path) -> bool {
+        let Some(file_name) = path.file_name() else {
+            return false;
+        };
+        self.excluded_paths.iter().any(|(parent, excluded_name)| {
+            excluded_name == file_name
+                && fs::canonicalize(parent_dir(path)).is_ok_and(|dir| dir == *parent)
+        })
+    }
+
+    fn is_included(&self, relative_path: &Path) -> bool {
+        self.include.is_empty()
+            || self
+                .include
+                .iter()
+                .any(|pattern| matches_pattern(pattern, relative_path))
+    }
+}
+
+/// 收集目录中的所有文件, 遍历中的错误被忽略
+pub fn get_all_file_path(dir: &Path) -> Vec<PathBuf> {
+    walk(dir)
+        .filter_map(|event| match event {
+            Ok(WalkEvent::File(file_path)) => Some(file_path),
+            _ => None,
+        })
+        .collect()
+}
+
+/// 收集目录中的所有文件, 非严格模式下的错误通过 on_warning 报告
+pub fn walk_files(
+    dir: &Path,
+    options: &WalkOptions,
+    on_warning: &mut impl FnMut(Error),
+) -> Result<Vec<PathBuf>> {
+    walk_files_with_events(dir, options, &mut |event| {
+        if let WalkEvent::Warning(err) = event {
+            on_warning(err)
+        }
+    })
+}
+
+// 除警告外还报告每个被跳过的路径及原因
+pub(crate) fn walk_files_with_events(
+    dir: &Path,
+    options: &WalkOptions,
+    on_event: &mut impl FnMut(WalkEvent),
+) -> Result<Vec<PathBuf>> {
+    let mut file_paths = Vec::new();
+    visit_files(dir, options, &mut |event| match event {
+        WalkEvent::File(file_path) => file_paths.push(file_path),
+        event => on_event(event),
+    })?;
+    Ok(file_paths)
+}
+
+// 边遍历边通过 WalkEvent::File 报告找到的文件, 调用方不需要等待遍历结束
+pub(crate) fn visit_files(
+    dir: &Path,
+    options: &WalkOptions,
+    on_event: &mut impl FnMut(WalkEvent),
+) -> Result<()> {
+    for event in walk_with_options(dir, options.clone()) {
+        on_event(event?);
+    }
+    Ok(())
+}
+
+/// 按默认选项遍历目录
+pub fn walk(dir: &Path) -> Walk {
+    walk_with_options(dir, WalkOptions::default())
+}
+
+/// 按指定的选项遍历目录
+pub fn walk_with_options(dir: &Path, options: WalkOptions) -> Walk {
+    Walk {
+        options,
+        root: Some(dir.to_path_buf()),
+        stack: Vec::new(),
+        finished: false,
+    }
+}
+
+/// 按需遍历目录的迭代器, 深度优先, 顺序与 visit_files 相同
+/// 非严格模式下错误作为 WalkEvent::Warning 返回, 严格模式下返回 Err 并结束遍历
+pub struct Walk {
+    options: WalkOptions,
+    root: Option<PathBuf>,
+    // 从根目录到当前目录的各级祖先目录
+    stack: Vec<OpenDir>,
+    finished: bool,
+}
+
+// 正在遍历的目录, 以及它的路径, 相对于根目录的路径和跟随符号链接时的真实路径
+struct OpenDir {
+    entries: fs::ReadDir,
+    dir: PathBuf,
+    relative_dir: PathBuf,
+    canonical_dir: Option<PathBuf>,
+}
+
+impl Iterator for Walk {
+    type Item = Result<WalkEvent>;
+
+    fn next(&mut self) -> Option<Result<WalkEvent>> {
+        if self.finished {
+            return None;
+        }
+        if let Some(root) = self.root.take() {
+            let canonical_root = self
+                .options
+                .follow_symlinks
+                .then(|| fs::canonicalize(&root).ok())
+                .flatten();
+            if let Some(event) = self.open_dir(root, PathBuf::new(), canonical_root) {
+                return Some(event);
+            }
+        }
+        loop {
+            let open_dir = self.stack.last_mut()?;
+            match open_dir.entries.next() {
+                None => {
+                    self.stack.pop();
+                }
+                Some(Err(err)) => {
+                    let err = Error::io(&open_dir.dir, err);
+                    return Some(self.warn(err));
+                }
+                Some(Ok(entry)) => {
+                    let relative_path = open_dir.relative_dir.join(entry.file_name());
+                    if let Some(event) = self.visit(entry, relative_path) {
+                        return Some(event);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Walk {
+    // 进入子目录时不产生事件, 返回 None
+    fn visit(&mut self, entry: fs::DirEntry, relative_path: PathBuf) -> Option<Result<WalkEvent>> {
+        let path = entry.path();
+        let options = &self.options;
+
+        if options.skip_hidden && entry.file_name().to_string_lossy().starts_with('.') {
+            return Some(Ok(skip(path, SkipReason::Hidden)));
+        }
+        if options.is_excluded(&relative_path) || options.is_excluded_path(&path) {
+            return Some(Ok(skip(path, SkipReason::Excluded)));
+        }
+
+        let file_type = match entry.file_type() {
+            Ok(file_type) => file_type,
+            Err(err) => return Some(self.warn(Error::io(&path, err))),
+        };
+        let (is_file, is_dir) = if file_type.is_symlink() {
+            if !options.follow_symlinks {
+                return Some(Ok(skip(path, SkipReason::Symlink)));
+            }
+            match fs::metadata(&path) {
+                Ok(metadata) => (metadata.is_file(), metadata.is_dir()),
+                Err(err) => return Some(self.warn(Error::io(&path, err))),
+            }
+        } else {
+            (file_type.is_file(), file_type.is_dir())
+        };
+
+        if is_file {
+            if options.is_included(&relative_path) {
+                Some(Ok(WalkEvent::File(path)))
+            } else {
+                Some(Ok(skip(path, SkipReason::NotIncluded)))
+            }
+        } else if is_dir {
+            // 跟随符号链接时, 指向某一级祖先目录的链接才会形成循环
+            // 多个链接指向同一个非祖先目录时各遍历一次
+            let mut canonical_dir = None;
+            if options.follow_symlinks {
+                match fs::canonicalize(&path) {
+                    Ok(canonical_path) => {
+                        if self.stack.iter().any(|open_dir| {
+                            open_dir.canonical_dir.as_ref() == Some(&canonical_path)
+                        }) {
+                            return Some(self.warn(Error::SymlinkLoop(path)));
+                        }
+                        canonical_dir = Some(canonical_path);
+                    }
+                    Err(err) => return Some(self.warn(Error::io(&path, err))),
+                }
+            }
+            self.open_dir(path, relative_path, canonical_dir)
+        } else {
+            Some(Ok(skip(path, SkipReason::SpecialFile)))
+        }
+    }
+
+    fn open_dir(
+        &mut self,
+        dir: PathBuf,
+        relative_dir: PathBuf,
+        canonical_dir: Option<PathBuf>,
+    ) -> Option<Result<WalkEvent>> {
+        match fs::read_dir(&dir) {
+            Ok(entries) => {
+                self.stack.push(OpenDir {
+                    entries,
+                    dir,
+                    relative_dir,
+                    canonical_dir,
+                });
+                None
+            }
+            Err(err) => Some(self.warn(Error::io(&dir, err))),
+        }
+    }
+
+    // 非严格模式下把错误作为警告报告并继续遍历
+    fn warn(&mut self, err: Error) -> Result<WalkEvent> {
+        if self.options.strict {
+            self.finished = true;
+            Err(err)
+        } else {
+            Ok(WalkEvent::Warning(err))
+        }
+    }
+}
+
+fn parent_dir(file_path: &Path) -> &Path {
+    match file_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+fn skip(path: PathBuf, reason: SkipReason) -> WalkEvent {
+    WalkEvent::Skipped { path, reason }
+}
+
+/// 公开接口中使用的匹配模式, 匹配规则与 --include 相同
+#[derive(Clone, Debug)]
+pub struct GlobPattern(Pattern);
+
+impl GlobPattern {
+    pub fn new(pattern: &str) -> Result<GlobPattern> {
+        Ok(GlobPattern(parse_pattern(pattern)?))
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+
+    pub fn matches(&self, relative_path: &Path) -> bool {
+        matches_pattern(&self.0, relative_path)
+    }
+}
+
+pub(crate) fn parse_pattern(pattern: &str) -> Result<Pattern> {
+    Pattern::new(pattern).map_err(|err| Error::InvalidPattern {
+        pattern: pattern.to_string(),
+        message: err.to_string(),
+    })
+}
+
+// 不含'/'的模式匹配文件名, 否则匹配相对路径
+pub(crate) fn matches_pattern(pattern: &Pattern, relative_path: &Path) -> bool {
+    if pattern.as_str().contains('/') {
+        let relative_path = relative_path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let match_options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+        pattern.matches_with(&relative_path, match_options)
+    } else {
+        match relative_path.file_name() {
+            Some(file_name) => pattern.matches(&file_name.to_string_lossy()),
+            None => false,
+        }
+    }
+}